@@ -0,0 +1,89 @@
+/* Timing of the interpreter on the programs of test_eval.pst, scaled up: `cargo bench` */
+use pistolet::{parse_to_ast, Interpreter, VarValue};
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 10;
+
+/*
+ * Best times of the evaluator that cloned the syntax tree and every frame at each
+ * call, the one before 9e25795, on these programs in a release build. The speedup
+ * printed against them is only meaningful on comparable hardware.
+ */
+const FIB_BASELINE: Duration = Duration::from_millis(1280);
+const FACTORIAL_BASELINE: Duration = Duration::from_millis(116);
+const NESTED_LOOPS_BASELINE: Duration = Duration::from_millis(145);
+
+const FIB: &str = "
+fun fib (n: int) -> int {
+    if (n =? 0) {
+        return 0.
+    } {
+        if (n =? 1) {
+            return 1.
+        } {
+            return fib(n-1) + fib(n-2).
+        }
+    }
+}
+return fib(25).";
+
+const FACTORIAL: &str = "
+fun factorial (n: int) -> int {
+    if (n =? 1) {
+        return 1.
+    } {
+        return n * factorial(n - 1).
+    }
+}
+let mut total = 0.
+let mut i = 0.
+loop {
+    i := i + 1.
+    total := total + factorial(30).
+} ~> (i =? 1000)
+return total.";
+
+const NESTED_LOOPS: &str = "
+let mut total = 0.
+let k : int = 0.
+loop {
+    let k : int = k + 1.
+    let k1 : int = 0.
+    loop {
+        let k1 : int = k1 + 1.
+        total := total + k1.
+    } ~> (k1 =? 300)
+} ~> (k =? 300)
+return total.";
+
+fn time(source: &str) -> (Duration, VarValue) {
+    let ast = parse_to_ast(source).expect("benchmark does not parse");
+    let start = Instant::now();
+    let result = Interpreter::new().run(ast);
+    let elapsed = start.elapsed();
+    match result {
+        Ok(Some(expr_value)) => (elapsed, expr_value.get_value()),
+        Ok(None) => panic!("benchmark finished without return"),
+        Err(err) => panic!("{}", err),
+    }
+}
+
+fn main() {
+    let programs = [
+        ("fib(25)", FIB, FIB_BASELINE),
+        ("factorial(30) x 1000", FACTORIAL, FACTORIAL_BASELINE),
+        ("nested loops", NESTED_LOOPS, NESTED_LOOPS_BASELINE),
+    ];
+    for (name, source, baseline) in programs {
+        let mut runs: Vec<(Duration, VarValue)> = (0..RUNS).map(|_| time(source)).collect();
+        runs.sort_by_key(|(elapsed, _)| *elapsed);
+        println!(
+            "{:<22} median {:>10.2?}   best {:>10.2?}   {:>5.1}x the cloning evaluator   = {}",
+            name,
+            runs[RUNS / 2].0,
+            runs[0].0,
+            baseline.as_secs_f64() / runs[0].0.as_secs_f64(),
+            runs[0].1
+        );
+    }
+}
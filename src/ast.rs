@@ -1,39 +1,435 @@
+use std::rc::Rc;
+
+/* Location of a node in the source: byte range plus the line and column it starts at */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize, line: usize, col: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            col,
+        }
+    }
+    pub fn merge(&self, other: Span) -> Span {
+        let (first, last) = if self.start <= other.start {
+            (*self, other)
+        } else {
+            (other, *self)
+        };
+        Span {
+            start: first.start,
+            end: first.end.max(last.end),
+            line: first.line,
+            col: first.col,
+        }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/*
+ * A type as written in an annotation or inferred by the checker. Names the
+ * parser does not know stay `Named`, the checker accepts them only if a `type`
+ * declaration in scope defines them.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Bool,
+    Str,
+    Array(Box<Type>),
+    Tuple(Vec<Type>),
+    Named(String),
+    Fun(Vec<Type>, Box<Type>), /* parameters, return type */
+    Var(usize),                /* not inferred yet, only the checker makes these */
+}
+
+impl Type {
+    pub fn from_name(name: &str) -> Type {
+        match name {
+            "int" => Type::Int,
+            "float" => Type::Float,
+            "bool" => Type::Bool,
+            "string" => Type::Str,
+            other => Type::Named(other.to_string()),
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Bool => write!(f, "bool"),
+            Type::Str => write!(f, "string"),
+            Type::Array(elem) => write!(f, "array<{}>", elem),
+            Type::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            }
+            Type::Named(name) => write!(f, "{}", name),
+            Type::Fun(paras, ret) => {
+                for para in paras {
+                    match para {
+                        Type::Fun(_, _) => write!(f, "({}) -> ", para)?,
+                        _ => write!(f, "{} -> ", para)?,
+                    }
+                }
+                match **ret {
+                    Type::Fun(_, _) => write!(f, "({})", ret),
+                    _ => write!(f, "{}", ret),
+                }
+            }
+            Type::Var(_) => write!(f, "_"),
+        }
+    }
+}
+
+/* Where a variable lives: `depth` frames out from the innermost one, at `index` in that frame */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
 #[derive(Debug, Clone)]
-enum PistoletValue {
-    Integer(i128),
-    Float(f64),
-    Boolean(bool),
-    Var(String),
-    Funcall(String, Vec<PistoletExpr>),
+pub enum PistoletValue {
+    Integer(i128, Span),
+    Float(f64, Span),
+    Boolean(bool, Span),
+    Str(String, Span),
+    Array(Vec<PistoletExpr>, Span),
+    Tuple(Vec<PistoletExpr>, Span),
+    Record(String, Vec<(String, PistoletExpr)>, Span), /* type, fields */
+    Var(String, Option<Slot>, Span), /* the slot is set by the resolver, none for a function or constructor name */
+    Funcall(String, Vec<PistoletExpr>, Span),
 }
 
 #[derive(Debug, Clone)]
-enum PistoletExpr {
+pub enum PistoletExpr {
     Val(PistoletValue),
-    Add(Box<PistoletExpr>, Box<PistoletExpr>),
-    Sub(Box<PistoletExpr>, Box<PistoletExpr>),
-    Mul(Box<PistoletExpr>, Box<PistoletExpr>),
-    Div(Box<PistoletExpr>, Box<PistoletExpr>),
-    And(Box<PistoletExpr>, Box<PistoletExpr>),
-    Orb(Box<PistoletExpr>, Box<PistoletExpr>),
-    Nand(Box<PistoletExpr>, Box<PistoletExpr>),
-    Eq(Box<PistoletExpr>, Box<PistoletExpr>),
-    Leq(Box<PistoletExpr>, Box<PistoletExpr>),
-    Req(Box<PistoletExpr>, Box<PistoletExpr>),
-    Left(Box<PistoletExpr>, Box<PistoletExpr>),
-    Right(Box<PistoletExpr>, Box<PistoletExpr>)
+    Neg(Box<PistoletExpr>, Span),
+    Not(Box<PistoletExpr>, Span),
+    Index(Box<PistoletExpr>, Box<PistoletExpr>, Span), /* array, index */
+    Field(Box<PistoletExpr>, String, Span),
+    Update(Box<PistoletExpr>, Vec<(String, PistoletExpr)>, Span), /* `{ record with fields }` */
+    Match(Box<PistoletExpr>, Vec<(Pattern, Option<PistoletExpr>, PistoletExpr)>, Span), /* arms: pattern, guard, value */
+    Lambda(Box<PistoletAST>, Option<Type>, Rc<PistoletAST>, Span), /* parameters, return type, body shared with its closures */
+    Call(Box<PistoletExpr>, Vec<PistoletExpr>, Span), /* a call of a function value */
+    Add(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Sub(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Mul(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Div(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    And(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Orb(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Nand(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Eq(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Leq(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Req(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Left(Box<PistoletExpr>, Box<PistoletExpr>, Span),
+    Right(Box<PistoletExpr>, Box<PistoletExpr>, Span)
+}
+
+/* the right side of a `type` declaration */
+#[derive(Debug, Clone)]
+pub enum TypeDef {
+    Record(Vec<(String, Type)>), /* field names and types, in declaration order */
+    Variant(Vec<(String, Vec<Type>)>), /* constructors and the types they take */
+}
+
+/*
+ * The names a `let` binds, `let (a, b) = ...` takes a tuple apart. The arms of
+ * a `match` may also test for literals and constructors, which can fail.
+ */
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Var(Rc<str>, usize, Span), /* name, shared with each binding made of it, index in the innermost frame set by the resolver */
+    Tuple(Vec<Pattern>, Span),
+    Wildcard(Span),
+    Literal(PistoletValue),
+    Ctor(String, Option<Box<Pattern>>, Span), /* `Circle r`, `Rect (w, h)` */
+}
+
+impl Pattern {
+    pub fn span(&self) -> Span {
+        match self {
+            Pattern::Var(_, _, span)
+            | Pattern::Tuple(_, span)
+            | Pattern::Wildcard(span)
+            | Pattern::Ctor(_, _, span) => *span,
+            Pattern::Literal(value) => value.span(),
+        }
+    }
+
+    /*
+     * One pattern per argument of a constructor taking `arity` of them. A
+     * constructor of several arguments is matched with a tuple of that many
+     * patterns or `_`, so `None` means the argument does not fit.
+     */
+    pub fn ctor_args(arg: Option<&Pattern>, arity: usize) -> Option<Vec<Pattern>> {
+        match (arg, arity) {
+            (None, 0) => Some(Vec::new()),
+            (Some(arg), 1) => Some(vec![arg.clone()]),
+            (Some(Pattern::Tuple(patterns, _)), n) if patterns.len() == n => Some(patterns.clone()),
+            (Some(Pattern::Wildcard(span)), n) if n > 1 => Some(vec![Pattern::Wildcard(*span); n]),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Pattern::Var(name, _, _) => write!(f, "{}", name),
+            Pattern::Tuple(patterns, _) => {
+                let items: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            }
+            Pattern::Wildcard(_) => write!(f, "_"),
+            Pattern::Literal(value) => write!(f, "{}", value),
+            Pattern::Ctor(name, None, _) => write!(f, "{}", name),
+            Pattern::Ctor(name, Some(arg), _) => match **arg {
+                Pattern::Ctor(_, Some(_), _) => write!(f, "{} ({})", name, arg),
+                _ => write!(f, "{} {}", name, arg),
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
-enum PistoletAST {
-    Seq(Vec<PistoletAST>),
-    Let(String, String, PistoletExpr),
-    If(PistoletExpr, Box<PistoletAST>, Box<PistoletAST>),
-    While(Box<PistoletAST>, PistoletExpr),
-    Return(PistoletExpr),
-    Varbind(String, String),
-    Paralist(Vec<PistoletAST>),
-    Fun(String, Box<PistoletAST>, String, Box<PistoletAST>),
-    PrintLine(PistoletExpr),
+#[allow(clippy::upper_case_acronyms)]
+pub enum PistoletAST {
+    Seq(Vec<PistoletAST>, Span),
+    TypeDef(String, TypeDef, Span),
+    Let(Pattern, bool, Option<Type>, PistoletExpr, Span), /* mutable, a missing type is inferred */
+    If(PistoletExpr, Box<PistoletAST>, Box<PistoletAST>, Span),
+    Match(PistoletExpr, Vec<(Pattern, Option<PistoletExpr>, PistoletAST)>, Span), /* arms: pattern, guard, block */
+    While(Box<PistoletAST>, PistoletExpr, Option<String>, Vec<(usize, Slot)>, Span), /* body, condition, label, carried bindings */
+    WhileDo(PistoletExpr, Box<PistoletAST>, Option<String>, Span), /* condition, body, label */
+    For(Rc<str>, PistoletExpr, Option<PistoletExpr>, Box<PistoletAST>, Option<String>, Span), /* variable, array or range start, range end, body, label */
+    Return(PistoletExpr, Span),
+    Break(Option<String>, Span),
+    Continue(Option<String>, Span),
+    Varbind(Rc<str>, Option<Type>, Span), /* the name is shared with each binding made of it */
+    Paralist(Vec<PistoletAST>, Span),
+    Fun(String, Box<PistoletAST>, Option<Type>, Rc<PistoletAST>, Span), /* the body is shared with its closures */
+    PrintLine(PistoletExpr, Span),
+    Assign(PistoletExpr, PistoletExpr, Span), /* target, value */
+    Call(PistoletExpr, Span),
     EOI,
 }
+
+impl PistoletValue {
+    pub fn span(&self) -> Span {
+        match self {
+            PistoletValue::Integer(_, span)
+            | PistoletValue::Float(_, span)
+            | PistoletValue::Boolean(_, span)
+            | PistoletValue::Str(_, span)
+            | PistoletValue::Array(_, span)
+            | PistoletValue::Tuple(_, span)
+            | PistoletValue::Record(_, _, span)
+            | PistoletValue::Var(_, _, span)
+            | PistoletValue::Funcall(_, _, span) => *span,
+        }
+    }
+    fn set_span(&mut self, new_span: Span) {
+        match self {
+            PistoletValue::Integer(_, span)
+            | PistoletValue::Float(_, span)
+            | PistoletValue::Boolean(_, span)
+            | PistoletValue::Str(_, span)
+            | PistoletValue::Array(_, span)
+            | PistoletValue::Tuple(_, span)
+            | PistoletValue::Record(_, _, span)
+            | PistoletValue::Var(_, _, span)
+            | PistoletValue::Funcall(_, _, span) => *span = new_span,
+        }
+    }
+}
+
+impl PistoletExpr {
+    pub fn span(&self) -> Span {
+        match self {
+            PistoletExpr::Val(value) => value.span(),
+            PistoletExpr::Neg(_, span) | PistoletExpr::Not(_, span) => *span,
+            PistoletExpr::Index(_, _, span)
+            | PistoletExpr::Field(_, _, span)
+            | PistoletExpr::Update(_, _, span)
+            | PistoletExpr::Match(_, _, span)
+            | PistoletExpr::Lambda(_, _, _, span)
+            | PistoletExpr::Call(_, _, span) => *span,
+            PistoletExpr::Add(_, _, span)
+            | PistoletExpr::Sub(_, _, span)
+            | PistoletExpr::Mul(_, _, span)
+            | PistoletExpr::Div(_, _, span)
+            | PistoletExpr::And(_, _, span)
+            | PistoletExpr::Orb(_, _, span)
+            | PistoletExpr::Nand(_, _, span)
+            | PistoletExpr::Eq(_, _, span)
+            | PistoletExpr::Leq(_, _, span)
+            | PistoletExpr::Req(_, _, span)
+            | PistoletExpr::Left(_, _, span)
+            | PistoletExpr::Right(_, _, span) => *span,
+        }
+    }
+    /* a parenthesized expression reaches over its brackets, so the expressions around it do too */
+    pub(crate) fn set_span(&mut self, new_span: Span) {
+        match self {
+            PistoletExpr::Val(value) => value.set_span(new_span),
+            PistoletExpr::Neg(_, span) | PistoletExpr::Not(_, span) => *span = new_span,
+            PistoletExpr::Index(_, _, span)
+            | PistoletExpr::Field(_, _, span)
+            | PistoletExpr::Update(_, _, span)
+            | PistoletExpr::Match(_, _, span)
+            | PistoletExpr::Lambda(_, _, _, span)
+            | PistoletExpr::Call(_, _, span) => *span = new_span,
+            PistoletExpr::Add(_, _, span)
+            | PistoletExpr::Sub(_, _, span)
+            | PistoletExpr::Mul(_, _, span)
+            | PistoletExpr::Div(_, _, span)
+            | PistoletExpr::And(_, _, span)
+            | PistoletExpr::Orb(_, _, span)
+            | PistoletExpr::Nand(_, _, span)
+            | PistoletExpr::Eq(_, _, span)
+            | PistoletExpr::Leq(_, _, span)
+            | PistoletExpr::Req(_, _, span)
+            | PistoletExpr::Left(_, _, span)
+            | PistoletExpr::Right(_, _, span) => *span = new_span,
+        }
+    }
+}
+
+impl std::fmt::Display for PistoletValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PistoletValue::Integer(n, _) => write!(f, "{}", n),
+            PistoletValue::Float(n, _) => write!(f, "{:?}", n),
+            PistoletValue::Boolean(b, _) => write!(f, "{}", b),
+            PistoletValue::Str(s, _) => write!(f, "{:?}", s),
+            PistoletValue::Var(name, _, _) => write!(f, "{}", name),
+            PistoletValue::Array(expr_list, _) => {
+                let items: Vec<String> = expr_list.iter().map(|expr| expr.to_string()).collect();
+                write!(f, "[{}]", items.join(", "))
+            }
+            PistoletValue::Tuple(expr_list, _) => {
+                let items: Vec<String> = expr_list.iter().map(|expr| expr.to_string()).collect();
+                write!(f, "({})", items.join(", "))
+            }
+            PistoletValue::Record(name, fields, _) => {
+                write!(f, "{} {{ {} }}", name, show_fields(fields))
+            }
+            PistoletValue::Funcall(name, expr_list, _) => {
+                let args: Vec<String> = expr_list.iter().map(|expr| expr.to_string()).collect();
+                write!(f, "{}({})", name, args.join(", "))
+            }
+        }
+    }
+}
+
+/* fully parenthesized, so the grouping the parser chose is visible */
+impl std::fmt::Display for PistoletExpr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let (e1, op, e2) = match self {
+            PistoletExpr::Val(value) => return write!(f, "{}", value),
+            PistoletExpr::Neg(e, _) => return write!(f, "(-{})", e),
+            PistoletExpr::Not(e, _) => return write!(f, "(!{})", e),
+            PistoletExpr::Index(e, i, _) => return write!(f, "{}[{}]", e, i),
+            PistoletExpr::Field(e, field, _) => return write!(f, "{}.{}", e, field),
+            PistoletExpr::Update(e, fields, _) => {
+                return write!(f, "{{ {} with {} }}", e, show_fields(fields))
+            }
+            PistoletExpr::Lambda(para_list, return_type, _, _) => {
+                write!(f, "(fun ")?;
+                if let PistoletAST::Paralist(paras, _) = &**para_list {
+                    for para in paras {
+                        match para {
+                            PistoletAST::Varbind(name, Some(ty), _) => write!(f, "({}: {})", name, ty)?,
+                            PistoletAST::Varbind(name, None, _) => write!(f, "({})", name)?,
+                            _ => {}
+                        }
+                    }
+                }
+                if let Some(ty) = return_type {
+                    write!(f, " -> {}", ty)?;
+                }
+                return write!(f, " {{ ... }})");
+            }
+            PistoletExpr::Call(e, args, _) => {
+                let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+                return write!(f, "{}({})", e, args.join(", "));
+            }
+            PistoletExpr::Match(e, arms, _) => {
+                write!(f, "(match {} with", e)?;
+                for (pattern, guard, value) in arms {
+                    write!(f, " | {}", pattern)?;
+                    if let Some(guard) = guard {
+                        write!(f, " when {}", guard)?;
+                    }
+                    write!(f, " -> {}", value)?;
+                }
+                return write!(f, ")");
+            }
+            PistoletExpr::Add(e1, e2, _) => (e1, "+", e2),
+            PistoletExpr::Sub(e1, e2, _) => (e1, "-", e2),
+            PistoletExpr::Mul(e1, e2, _) => (e1, "*", e2),
+            PistoletExpr::Div(e1, e2, _) => (e1, "/", e2),
+            PistoletExpr::And(e1, e2, _) => (e1, "&&", e2),
+            PistoletExpr::Orb(e1, e2, _) => (e1, "||", e2),
+            PistoletExpr::Nand(e1, e2, _) => (e1, "~&&", e2),
+            PistoletExpr::Eq(e1, e2, _) => (e1, "=?", e2),
+            PistoletExpr::Leq(e1, e2, _) => (e1, "<=", e2),
+            PistoletExpr::Req(e1, e2, _) => (e1, ">=", e2),
+            PistoletExpr::Left(e1, e2, _) => (e1, "<", e2),
+            PistoletExpr::Right(e1, e2, _) => (e1, ">", e2),
+        };
+        write!(f, "({} {} {})", e1, op, e2)
+    }
+}
+
+fn show_fields(fields: &[(String, PistoletExpr)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(name, expr)| format!("{} = {}", name, expr))
+        .collect();
+    fields.join(", ")
+}
+
+impl PistoletAST {
+    pub fn span(&self) -> Span {
+        match self {
+            PistoletAST::Seq(_, span)
+            | PistoletAST::TypeDef(_, _, span)
+            | PistoletAST::Let(_, _, _, _, span)
+            | PistoletAST::If(_, _, _, span)
+            | PistoletAST::Match(_, _, span)
+            | PistoletAST::While(_, _, _, _, span)
+            | PistoletAST::WhileDo(_, _, _, span)
+            | PistoletAST::For(_, _, _, _, _, span)
+            | PistoletAST::Return(_, span)
+            | PistoletAST::Break(_, span)
+            | PistoletAST::Continue(_, span)
+            | PistoletAST::Varbind(_, _, span)
+            | PistoletAST::Paralist(_, span)
+            | PistoletAST::Fun(_, _, _, _, span)
+            | PistoletAST::PrintLine(_, span)
+            | PistoletAST::Assign(_, _, span)
+            | PistoletAST::Call(_, span) => *span,
+            PistoletAST::EOI => Span::default(),
+        }
+    }
+}
@@ -0,0 +1,1436 @@
+use crate::ast::*;
+use crate::exhaustive::missing_case;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone)]
+pub struct TypeErr {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TypeErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[Error] {}: {}", self.span, self.message)
+    }
+}
+
+/* something legal but probably wrong, it does not stop the program from running */
+#[derive(Debug, Clone)]
+pub struct TypeWarning {
+    pub message: String,
+    pub span: Span,
+}
+
+impl fmt::Display for TypeWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[Warning] {}: {}", self.span, self.message)
+    }
+}
+
+/* a function signature, generic over the variables in `vars` */
+#[derive(Debug, Clone)]
+struct Scheme {
+    vars: Vec<usize>,
+    para_names: Vec<String>,
+    para_types: Vec<Type>,
+    return_type: Type,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    var_list: HashMap<String, Type>,
+    mut_list: HashSet<String>, /* the variables of `var_list` bound by `let mut` */
+    func_list: HashMap<String, Scheme>,
+    type_list: HashMap<String, TypeDef>, /* with the types in it resolved */
+    ctor_list: HashMap<String, (String, Vec<Type>)>, /* constructor: its type and arguments */
+}
+
+type Snapshot = (Scope, Vec<Option<Type>>, Vec<Class>);
+
+/* the types a variable may still be solved to, each class narrower than the one before */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Class {
+    Any,
+    Ordered, /* `+` and comparisons */
+    Numeric, /* the other arithmetic operators */
+}
+
+impl Class {
+    fn admits(self, ty: &Type) -> bool {
+        match self {
+            Class::Any => true,
+            Class::Ordered => matches!(ty, Type::Int | Type::Float | Type::Str),
+            Class::Numeric => matches!(ty, Type::Int | Type::Float),
+        }
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Class::Any => write!(f, "_"),
+            Class::Ordered => write!(f, "int, float or string"),
+            Class::Numeric => write!(f, "int or float"),
+        }
+    }
+}
+
+/*
+ * Walks the whole program before it runs, so errors in branches that are never
+ * taken are still reported. Scopes mirror the interpreter: `if` branches, loop
+ * bodies and function bodies each get their own, and a `fun` is visible to the
+ * whole block it is defined in.
+ *
+ * Missing annotations are inferred Hindley-Milner style: every unknown type is a
+ * variable, uses of it are unified, and a `fun` is generalized once its body is
+ * checked so each call can use it at different types. Operators only work on
+ * some types, so variables they touch get a `Class` limiting what they may be
+ * solved to.
+ */
+#[derive(Debug, Clone)]
+pub struct Checker {
+    scopes: Vec<Scope>,
+    return_type: Option<Type>,
+    program_returns: HashSet<usize>, /* positions of the `return`s that end the program, the only ones allowed outside a function */
+    loops: Vec<Option<String>>, /* labels of the loops around the statement checked, innermost last */
+    bindings: Vec<Option<Type>>,
+    classes: Vec<Class>,
+    declared: HashMap<usize, Scheme>, /* hoisted functions not checked yet, by source position */
+    signatures: Vec<(Span, String, Scheme)>,
+    errors: Vec<TypeErr>,
+    warnings: Vec<TypeWarning>,
+}
+
+impl Checker {
+    pub fn new() -> Checker {
+        Checker {
+            scopes: vec![Scope::default()],
+            return_type: None,
+            program_returns: HashSet::new(),
+            loops: Vec::new(),
+            bindings: Vec::new(),
+            classes: Vec::new(),
+            declared: HashMap::new(),
+            signatures: Vec::new(),
+            errors: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /* checks a program against the global scope, which keeps its bindings only if there are no errors */
+    pub fn check(&mut self, ast: &PistoletAST) -> Result<(), Vec<TypeErr>> {
+        let saved = self.save();
+        self.signatures.clear();
+        self.warnings.clear();
+        let term_list = match ast {
+            PistoletAST::Seq(term_list, _) => &term_list[..],
+            other => std::slice::from_ref(other),
+        };
+        tail_returns(term_list, &mut self.program_returns);
+        self.check_seq(term_list);
+        self.finish(saved)
+    }
+
+    pub fn check_expr(&mut self, expr: &PistoletExpr) -> Result<String, Vec<TypeErr>> {
+        let saved = self.save();
+        self.warnings.clear();
+        let expr_type = self.expr_type(expr);
+        self.finish(saved)?;
+        Ok(self.type_name(&expr_type, &mut Vec::new()))
+    }
+
+    /* the inferred signature of every `fun` in the last checked program, in source order */
+    pub fn signatures(&self) -> Vec<String> {
+        let mut signatures: Vec<&(Span, String, Scheme)> = self.signatures.iter().collect();
+        signatures.sort_by_key(|(span, _, _)| span.start);
+        signatures
+            .into_iter()
+            .map(|(_, func_name, scheme)| self.render(func_name, scheme))
+            .collect()
+    }
+
+    /* warnings of the last check, in source order */
+    pub fn warnings(&self) -> &[TypeWarning] {
+        &self.warnings
+    }
+
+    /* signatures of the functions defined at the top level, sorted by name */
+    pub fn functions(&self) -> Vec<String> {
+        let mut func_names: Vec<&String> = self.scopes[0].func_list.keys().collect();
+        func_names.sort();
+        func_names
+            .into_iter()
+            .map(|func_name| self.render(func_name, &self.scopes[0].func_list[func_name]))
+            .collect()
+    }
+
+    fn save(&self) -> Snapshot {
+        (self.scopes[0].clone(), self.bindings.clone(), self.classes.clone())
+    }
+
+    fn finish(&mut self, saved: Snapshot) -> Result<(), Vec<TypeErr>> {
+        self.scopes.truncate(1);
+        self.return_type = None;
+        self.program_returns.clear();
+        self.declared.clear();
+        if self.errors.is_empty() {
+            self.warnings.sort_by_key(|warning| warning.span.start);
+            Ok(())
+        } else {
+            let (global, bindings, classes) = saved;
+            self.scopes[0] = global;
+            self.bindings = bindings;
+            self.classes = classes;
+            self.errors.sort_by_key(|err| err.span.start);
+            self.warnings.clear();
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn error(&mut self, message: String, span: Span) {
+        self.errors.push(TypeErr { message, span });
+    }
+
+    fn warn(&mut self, message: String, span: Span) {
+        self.warnings.push(TypeWarning { message, span });
+    }
+
+    fn find_var(&self, name: &str) -> Option<&Type> {
+        self.scopes.iter().rev().find_map(|scope| scope.var_list.get(name))
+    }
+
+    /* whether the binding `name` refers to may be assigned, `None` if there is none */
+    fn is_mutable(&self, name: &str) -> Option<bool> {
+        self.scopes
+            .iter()
+            .rev()
+            .find(|scope| scope.var_list.contains_key(name))
+            .map(|scope| scope.mut_list.contains(name))
+    }
+
+    fn find_func(&self, name: &str) -> Option<&Scheme> {
+        self.scopes.iter().rev().find_map(|scope| scope.func_list.get(name))
+    }
+
+    fn find_type(&self, name: &str) -> Option<&TypeDef> {
+        self.scopes.iter().rev().find_map(|scope| scope.type_list.get(name))
+    }
+
+    fn find_record(&self, name: &str) -> Option<Vec<(String, Type)>> {
+        match self.find_type(name) {
+            Some(TypeDef::Record(fields)) => Some(fields.clone()),
+            _ => None,
+        }
+    }
+
+    fn find_ctor(&self, name: &str) -> Option<(String, Vec<Type>)> {
+        self.scopes.iter().rev().find_map(|scope| scope.ctor_list.get(name)).cloned()
+    }
+
+    /* every constructor of the type `ctor` makes, with how many arguments each takes */
+    fn siblings(&self, ctor: &str) -> Option<Vec<(String, usize)>> {
+        let (type_name, _) = self.find_ctor(ctor)?;
+        match self.find_type(&type_name) {
+            Some(TypeDef::Variant(ctors)) => Some(
+                ctors
+                    .iter()
+                    .map(|(ctor, args)| (ctor.clone(), args.len()))
+                    .collect(),
+            ),
+            _ => None,
+        }
+    }
+
+    /* the visible record types with a field of this name, innermost declaration of each name */
+    fn records_with_field(&self, field: &str) -> Vec<String> {
+        let mut seen = Vec::new();
+        let mut found = Vec::new();
+        for scope in self.scopes.iter().rev() {
+            let mut names: Vec<&String> = scope.type_list.keys().collect();
+            names.sort();
+            for name in names {
+                if seen.contains(name) {
+                    continue;
+                }
+                seen.push(name.clone());
+                if let TypeDef::Record(fields) = &scope.type_list[name] {
+                    if fields.iter().any(|(f, _)| f == field) {
+                        found.push(name.clone());
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /*
+     * The declaration of the record `ty` must be to have `field`. A type not
+     * inferred yet becomes the only record type with that field, if there is one.
+     */
+    fn record_of(&mut self, ty: &Type, field: &str, span: Span) -> Option<(String, Vec<(String, Type)>)> {
+        let ty = self.resolve(ty);
+        if let Type::Var(_) = ty {
+            match self.records_with_field(field).as_slice() {
+                [name] => {
+                    let record = Type::Named(name.clone());
+                    if !self.unify(&ty, &record) {
+                        self.error(format!("{} has no field `{}`", self.show(&ty), field), span);
+                        return None;
+                    }
+                    return self.record_of(&record, field, span);
+                }
+                [] => self.error(format!("no record type has a field `{}`", field), span),
+                names => self.error(
+                    format!(
+                        "field `{}` could belong to any of {}, annotate which one",
+                        field,
+                        names.join(", ")
+                    ),
+                    span,
+                ),
+            }
+            return None;
+        }
+        let fields = match &ty {
+            Type::Named(name) => self.find_record(name),
+            _ => None,
+        };
+        match (ty, fields) {
+            (Type::Named(name), Some(fields)) => Some((name, fields)),
+            (ty, _) => {
+                self.error(format!("{} has no field `{}`", self.show(&ty), field), span);
+                None
+            }
+        }
+    }
+
+    /* checks `name = expr` fields against a record declaration, each may be set once */
+    fn check_fields(&mut self, record: &str, declared: &[(String, Type)], fields: &[(String, PistoletExpr)]) {
+        let mut seen: Vec<&str> = Vec::new();
+        for (field, expr) in fields {
+            let expr_type = self.expr_type(expr);
+            if seen.contains(&field.as_str()) {
+                self.error(format!("field `{}` is set twice", field), expr.span());
+                continue;
+            }
+            seen.push(field);
+            match declared.iter().find(|(name, _)| name == field) {
+                Some((_, field_type)) => {
+                    if !self.unify(&expr_type, field_type) {
+                        self.error(
+                            format!(
+                                "field `{}` of `{}` must be {} but is {}",
+                                field,
+                                record,
+                                self.show(field_type),
+                                self.show(&expr_type)
+                            ),
+                            expr.span(),
+                        );
+                    }
+                }
+                None => self.error(format!("`{}` has no field `{}`", record, field), expr.span()),
+            }
+        }
+    }
+
+    fn fresh(&mut self, class: Class) -> Type {
+        self.bindings.push(None);
+        self.classes.push(class);
+        Type::Var(self.bindings.len() - 1)
+    }
+
+    /* follows solved variables until reaching a concrete type or an unsolved variable */
+    fn resolve(&self, ty: &Type) -> Type {
+        let mut ty = ty.clone();
+        while let Type::Var(var) = ty {
+            match &self.bindings[var] {
+                Some(bound) => ty = bound.clone(),
+                None => break,
+            }
+        }
+        ty
+    }
+
+    fn unify(&mut self, t1: &Type, t2: &Type) -> bool {
+        let (t1, t2) = (self.resolve(t1), self.resolve(t2));
+        match (&t1, &t2) {
+            _ if t1 == t2 => true,
+            (Type::Var(var), other) | (other, Type::Var(var)) => {
+                if self.occurs(*var, other) || !self.require(self.classes[*var], other) {
+                    return false;
+                }
+                self.bindings[*var] = Some(other.clone());
+                true
+            }
+            (Type::Array(elem1), Type::Array(elem2)) => self.unify(elem1, elem2),
+            (Type::Tuple(items1), Type::Tuple(items2)) if items1.len() == items2.len() => items1
+                .iter()
+                .zip(items2)
+                .all(|(item1, item2)| self.unify(item1, item2)),
+            (Type::Fun(paras1, ret1), Type::Fun(paras2, ret2)) if paras1.len() == paras2.len() => {
+                paras1
+                    .iter()
+                    .zip(paras2)
+                    .all(|(para1, para2)| self.unify(para1, para2))
+                    && self.unify(ret1, ret2)
+            }
+            _ => false,
+        }
+    }
+
+    /* binding a variable to a type containing itself would make an infinite type */
+    fn occurs(&self, var: usize, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(other) => other == var,
+            Type::Array(elem) => self.occurs(var, &elem),
+            Type::Tuple(items) => items.iter().any(|item| self.occurs(var, item)),
+            Type::Fun(paras, ret) => {
+                paras.iter().any(|para| self.occurs(var, para)) || self.occurs(var, &ret)
+            }
+            _ => false,
+        }
+    }
+
+    fn free_vars(&self, ty: &Type, vars: &mut Vec<usize>) {
+        match self.resolve(ty) {
+            Type::Var(var) if !vars.contains(&var) => vars.push(var),
+            Type::Array(elem) => self.free_vars(&elem, vars),
+            Type::Tuple(items) => {
+                for item in items.iter() {
+                    self.free_vars(item, vars);
+                }
+            }
+            Type::Fun(paras, ret) => {
+                for para in paras.iter() {
+                    self.free_vars(para, vars);
+                }
+                self.free_vars(&ret, vars);
+            }
+            _ => {}
+        }
+    }
+
+    fn require(&mut self, class: Class, ty: &Type) -> bool {
+        match self.resolve(ty) {
+            Type::Var(var) => {
+                self.classes[var] = self.classes[var].max(class);
+                true
+            }
+            other => class.admits(&other),
+        }
+    }
+
+    /* how a type reads in an error message */
+    fn show(&self, ty: &Type) -> String {
+        match self.resolve(ty) {
+            Type::Var(var) => self.classes[var].to_string(),
+            Type::Array(elem) => format!("array<{}>", self.show(&elem)),
+            Type::Tuple(items) => {
+                let items: Vec<String> = items.iter().map(|item| self.show(item)).collect();
+                format!("({})", items.join(", "))
+            }
+            Type::Fun(paras, ret) => self.fun_name(&paras, &ret, &mut |ty| self.show(ty)),
+            other => other.to_string(),
+        }
+    }
+
+    /* `a -> b -> c`, with parentheses around parameters and results that are functions themselves */
+    fn fun_name(&self, paras: &[Type], ret: &Type, name: &mut dyn FnMut(&Type) -> String) -> String {
+        let mut parts = Vec::new();
+        for ty in paras.iter().chain(Some(ret)) {
+            let ty = self.resolve(ty);
+            match ty {
+                Type::Fun(_, _) => parts.push(format!("({})", name(&ty))),
+                _ => parts.push(name(&ty)),
+            }
+        }
+        parts.join(" -> ")
+    }
+
+    /* like `show`, but unsolved variables are named 'a, 'b, ... in order of appearance */
+    fn type_name(&self, ty: &Type, names: &mut Vec<usize>) -> String {
+        match self.resolve(ty) {
+            Type::Var(var) => {
+                let index = names.iter().position(|v| *v == var).unwrap_or_else(|| {
+                    names.push(var);
+                    names.len() - 1
+                });
+                var_name(index)
+            }
+            Type::Array(elem) => format!("array<{}>", self.type_name(&elem, names)),
+            Type::Tuple(items) => {
+                let items: Vec<String> =
+                    items.iter().map(|item| self.type_name(item, names)).collect();
+                format!("({})", items.join(", "))
+            }
+            Type::Fun(paras, ret) => {
+                self.fun_name(&paras, &ret, &mut |ty| self.type_name(ty, names))
+            }
+            other => other.to_string(),
+        }
+    }
+
+    fn render(&self, func_name: &str, scheme: &Scheme) -> String {
+        let mut names = Vec::new();
+        let mut signature = format!("fun {} ", func_name);
+        for (para_name, para_type) in scheme.para_names.iter().zip(&scheme.para_types) {
+            signature += &format!("({}: {})", para_name, self.type_name(para_type, &mut names));
+        }
+        signature += &format!(" -> {}", self.type_name(&scheme.return_type, &mut names));
+        let classes: Vec<String> = names
+            .iter()
+            .enumerate()
+            .filter(|(_, var)| self.classes[**var] != Class::Any)
+            .map(|(index, var)| format!("{} is {}", var_name(index), self.classes[*var]))
+            .collect();
+        if !classes.is_empty() {
+            signature += &format!(" where {}", classes.join(", "));
+        }
+        signature
+    }
+
+    /* annotations may only name types that exist, anything else is reported and inferred instead */
+    fn resolve_annotation(&mut self, ty: &Option<Type>, span: Span) -> Type {
+        match ty {
+            Some(ty) => self.resolve_type(ty, span),
+            None => self.fresh(Class::Any),
+        }
+    }
+
+    fn resolve_type(&mut self, ty: &Type, span: Span) -> Type {
+        match ty {
+            Type::Named(name) if self.find_type(name).is_some() => ty.clone(),
+            Type::Named(name) => {
+                self.error(format!("unknown type `{}`", name), span);
+                self.fresh(Class::Any)
+            }
+            Type::Array(elem) => Type::Array(Box::new(self.resolve_type(elem, span))),
+            Type::Tuple(items) => {
+                Type::Tuple(items.iter().map(|item| self.resolve_type(item, span)).collect())
+            }
+            Type::Fun(paras, ret) => Type::Fun(
+                paras.iter().map(|para| self.resolve_type(para, span)).collect(),
+                Box::new(self.resolve_type(ret, span)),
+            ),
+            other => other.clone(),
+        }
+    }
+
+    /* the monomorphic signature a function has while its own body is checked */
+    fn declare(&mut self, para_list: &PistoletAST, return_type: &Option<Type>, span: Span) -> Scheme {
+        let (para_names, para_types) = para_to_vec(para_list)
+            .into_iter()
+            .map(|(para_name, para_type, para_span)| {
+                (para_name, self.resolve_annotation(&para_type, para_span))
+            })
+            .unzip();
+        let return_type = self.resolve_annotation(return_type, span);
+        Scheme {
+            vars: Vec::new(),
+            para_names,
+            para_types,
+            return_type,
+        }
+    }
+
+    /* quantifies the variables of a checked function that nothing around it refers to */
+    fn generalize(&self, func_name: &str, scheme: Scheme) -> Scheme {
+        let mut env_vars = Vec::new();
+        let innermost = self.scopes.len() - 1;
+        for (depth, scope) in self.scopes.iter().enumerate() {
+            for var_type in scope.var_list.values() {
+                self.free_vars(var_type, &mut env_vars);
+            }
+            for (name, other) in scope.func_list.iter() {
+                if depth == innermost && name == func_name {
+                    continue;
+                }
+                let mut other_vars = Vec::new();
+                for ty in other.para_types.iter().chain(Some(&other.return_type)) {
+                    self.free_vars(ty, &mut other_vars);
+                }
+                env_vars.extend(other_vars.into_iter().filter(|var| !other.vars.contains(var)));
+            }
+        }
+        if let Some(return_type) = &self.return_type {
+            self.free_vars(return_type, &mut env_vars);
+        }
+        let mut vars = Vec::new();
+        for ty in scheme.para_types.iter().chain(Some(&scheme.return_type)) {
+            self.free_vars(ty, &mut vars);
+        }
+        vars.retain(|var| !env_vars.contains(var));
+        Scheme { vars, ..scheme }
+    }
+
+    /* a copy of the signature with fresh variables for the generic ones */
+    fn instantiate(&mut self, scheme: &Scheme) -> (Vec<Type>, Type) {
+        let mut fresh_vars = HashMap::new();
+        for var in scheme.vars.iter() {
+            let fresh = self.fresh(self.classes[*var]);
+            fresh_vars.insert(*var, fresh);
+        }
+        (
+            scheme
+                .para_types
+                .iter()
+                .map(|ty| self.substitute(ty, &fresh_vars))
+                .collect(),
+            self.substitute(&scheme.return_type, &fresh_vars),
+        )
+    }
+
+    fn substitute(&self, ty: &Type, fresh_vars: &HashMap<usize, Type>) -> Type {
+        match self.resolve(ty) {
+            Type::Var(var) => fresh_vars.get(&var).cloned().unwrap_or(Type::Var(var)),
+            Type::Array(elem) => Type::Array(Box::new(self.substitute(&elem, fresh_vars))),
+            Type::Tuple(items) => Type::Tuple(
+                items.iter().map(|item| self.substitute(item, fresh_vars)).collect(),
+            ),
+            Type::Fun(paras, ret) => Type::Fun(
+                paras.iter().map(|para| self.substitute(para, fresh_vars)).collect(),
+                Box::new(self.substitute(&ret, fresh_vars)),
+            ),
+            other => other,
+        }
+    }
+
+    /* functions every program can call unless it defines its own */
+    fn builtin(&mut self, func_name: &str) -> Option<Scheme> {
+        let (para_names, return_type) = match func_name {
+            "len" => (vec!["a"], Type::Int),
+            "push" => (vec!["a", "x"], Type::Int), /* returns the new length */
+            _ => return None,
+        };
+        let elem = self.fresh(Class::Any);
+        let mut para_types = vec![Type::Array(Box::new(elem.clone()))];
+        if para_names.len() > 1 {
+            para_types.push(elem);
+        }
+        Some(Scheme {
+            vars: Vec::new(),
+            para_names: para_names.into_iter().map(String::from).collect(),
+            para_types,
+            return_type,
+        })
+    }
+
+    /* a function body, with the parameters in scope and `return` checked against the signature */
+    fn check_body(&mut self, scheme: &Scheme, body: &PistoletAST) {
+        let mut scope = Scope::default();
+        for (para_name, para_type) in scheme.para_names.iter().zip(&scheme.para_types) {
+            scope.var_list.insert(para_name.clone(), para_type.clone());
+        }
+        let outer_return = self.return_type.replace(scheme.return_type.clone());
+        /* `break` cannot leave a function */
+        let outer_loops = std::mem::take(&mut self.loops);
+        self.scopes.push(scope);
+        self.check_block(body);
+        self.scopes.pop();
+        self.loops = outer_loops;
+        self.return_type = outer_return;
+    }
+
+    /* resolves the types a declaration mentions and makes its constructors visible */
+    fn declare_type(&mut self, type_name: &str, type_def: &TypeDef, span: Span) -> TypeDef {
+        match type_def {
+            TypeDef::Record(fields) => {
+                let mut resolved: Vec<(String, Type)> = Vec::new();
+                for (field, field_type) in fields {
+                    if resolved.iter().any(|(name, _)| name == field) {
+                        self.error(format!("field `{}` is declared twice", field), span);
+                        continue;
+                    }
+                    let field_type = self.resolve_type(field_type, span);
+                    resolved.push((field.clone(), field_type));
+                }
+                TypeDef::Record(resolved)
+            }
+            TypeDef::Variant(ctors) => {
+                let mut resolved: Vec<(String, Vec<Type>)> = Vec::new();
+                for (ctor, args) in ctors {
+                    if resolved.iter().any(|(name, _)| name == ctor) {
+                        self.error(format!("constructor `{}` is declared twice", ctor), span);
+                        continue;
+                    }
+                    let args: Vec<Type> = args.iter().map(|arg| self.resolve_type(arg, span)).collect();
+                    let ctor_list = &mut self.scopes.last_mut().unwrap().ctor_list;
+                    ctor_list.insert(ctor.clone(), (type_name.to_string(), args.clone()));
+                    resolved.push((ctor.clone(), args));
+                }
+                TypeDef::Variant(resolved)
+            }
+        }
+    }
+
+    /* warns when some value of the matched type is not handled by any arm without a guard */
+    fn check_exhaustive(&mut self, arms: &[(&Pattern, bool)], span: Span) {
+        let patterns: Vec<&Pattern> = arms
+            .iter()
+            .filter(|(_, guarded)| !guarded)
+            .map(|(pattern, _)| *pattern)
+            .collect();
+        if let Some(missing) = missing_case(&patterns, &|ctor| self.siblings(ctor)) {
+            self.warn(
+                format!("match is not exhaustive, `{}` is not handled", missing),
+                span,
+            );
+        }
+    }
+
+    fn check_block(&mut self, ast: &PistoletAST) {
+        self.scopes.push(Scope::default());
+        if let PistoletAST::Seq(term_list, _) = ast {
+            self.check_seq(term_list);
+        }
+        self.scopes.pop();
+    }
+
+    fn check_seq(&mut self, term_list: &[PistoletAST]) {
+        /* types are visible to the whole block, and may refer to each other */
+        for term in term_list {
+            if let PistoletAST::TypeDef(type_name, _, _) = term {
+                let type_list = &mut self.scopes.last_mut().unwrap().type_list;
+                type_list.insert(type_name.clone(), TypeDef::Record(Vec::new()));
+            }
+        }
+        for term in term_list {
+            if let PistoletAST::TypeDef(type_name, type_def, span) = term {
+                let type_def = self.declare_type(type_name, type_def, *span);
+                let type_list = &mut self.scopes.last_mut().unwrap().type_list;
+                type_list.insert(type_name.clone(), type_def);
+            }
+        }
+        for term in term_list {
+            if let PistoletAST::Fun(func_name, para_list, return_type, _, span) = term {
+                let scheme = self.declare(para_list, return_type, *span);
+                self.declared.insert(span.start, scheme.clone());
+                self.scopes.last_mut().unwrap().func_list.insert(func_name.clone(), scheme);
+            }
+        }
+        for term in term_list {
+            self.check_ast(term);
+        }
+    }
+
+    fn check_ast(&mut self, ast: &PistoletAST) {
+        match ast {
+            PistoletAST::Seq(_, _) => self.check_block(ast),
+            PistoletAST::Let(pattern, mutable, var_type, var_expr, span) => {
+                let declared = var_type
+                    .as_ref()
+                    .map(|_| self.resolve_annotation(var_type, *span));
+                let expr_type = self.expr_type(var_expr);
+                let var_type = match declared {
+                    Some(var_type) => {
+                        if !self.unify(&expr_type, &var_type) {
+                            self.error(
+                                format!(
+                                    "`{}` is declared {} but is bound to {}",
+                                    pattern,
+                                    self.show(&var_type),
+                                    self.show(&expr_type)
+                                ),
+                                var_expr.span(),
+                            );
+                        }
+                        var_type
+                    }
+                    None => expr_type,
+                };
+                self.bind_pattern(pattern, var_type);
+                if *mutable {
+                    self.mark_mutable(pattern);
+                }
+            }
+            PistoletAST::If(expr, branch_true, branch_false, _) => {
+                self.expect_type(expr, Type::Bool, "condition");
+                self.check_block(branch_true);
+                self.check_block(branch_false);
+            }
+            PistoletAST::Match(expr, arms, span) => {
+                let ty = self.expr_type(expr);
+                for (pattern, guard, body) in arms {
+                    self.scopes.push(Scope::default());
+                    self.bind_pattern(pattern, ty.clone());
+                    if let Some(guard) = guard {
+                        self.expect_type(guard, Type::Bool, "guard");
+                    }
+                    self.check_block(body);
+                    self.scopes.pop();
+                }
+                let arms: Vec<(&Pattern, bool)> = arms
+                    .iter()
+                    .map(|(pattern, guard, _)| (pattern, guard.is_some()))
+                    .collect();
+                self.check_exhaustive(&arms, *span);
+            }
+            PistoletAST::While(seq, expr, label, _, _) => {
+                /* the condition runs in the loop's scope, after the body */
+                self.scopes.push(Scope::default());
+                self.loops.push(label.clone());
+                if let PistoletAST::Seq(term_list, _) = &**seq {
+                    self.check_seq(term_list);
+                }
+                self.loops.pop();
+                self.expect_type(expr, Type::Bool, "loop condition");
+                self.scopes.pop();
+            }
+            PistoletAST::WhileDo(expr, seq, label, _) => {
+                self.expect_type(expr, Type::Bool, "loop condition");
+                self.check_loop_body(seq, label, Scope::default());
+            }
+            PistoletAST::For(var_name, start, end, seq, label, _) => {
+                let elem = match end {
+                    Some(end) => {
+                        self.expect_type(start, Type::Int, "range bound");
+                        self.expect_type(end, Type::Int, "range bound");
+                        Type::Int
+                    }
+                    None => {
+                        let found = self.expr_type(start);
+                        let elem = self.fresh(Class::Any);
+                        if !self.unify(&found, &Type::Array(Box::new(elem.clone()))) {
+                            self.error(
+                                format!("can only loop over arrays, not {}", self.show(&found)),
+                                start.span(),
+                            );
+                        }
+                        elem
+                    }
+                };
+                let mut scope = Scope::default();
+                scope.var_list.insert(var_name.to_string(), elem);
+                self.check_loop_body(seq, label, scope);
+            }
+            PistoletAST::Break(label, span) => self.check_jump("break", label, *span),
+            PistoletAST::Continue(label, span) => self.check_jump("continue", label, *span),
+            PistoletAST::Return(expr, span) => {
+                let found = self.expr_type(expr);
+                if let Some(expected) = self.return_type.clone() {
+                    if !self.unify(&found, &expected) {
+                        self.error(
+                            format!(
+                                "function returns {} but this returns {}",
+                                self.show(&expected),
+                                self.show(&found)
+                            ),
+                            *span,
+                        );
+                    }
+                } else if !self.program_returns.contains(&span.start) {
+                    self.error("`return` outside a function".to_string(), *span);
+                }
+            }
+            PistoletAST::Fun(func_name, para_list, return_type, fun_body, span) => {
+                let scheme = match self.declared.remove(&span.start) {
+                    Some(scheme) => scheme,
+                    None => self.declare(para_list, return_type, *span),
+                };
+                self.check_body(&scheme, fun_body);
+                if !always_returns(fun_body) {
+                    self.error(
+                        format!("function `{}` may finish without return", func_name),
+                        *span,
+                    );
+                }
+                let scheme = self.generalize(func_name, scheme);
+                self.signatures.push((*span, func_name.clone(), scheme.clone()));
+                self.scopes.last_mut().unwrap().func_list.insert(func_name.clone(), scheme);
+            }
+            PistoletAST::PrintLine(expr, _) | PistoletAST::Call(expr, _) => {
+                self.expr_type(expr);
+            }
+            PistoletAST::Assign(target, value, span) => {
+                let target_type = self.expr_type(target);
+                let value_type = self.expr_type(value);
+                if let PistoletExpr::Val(PistoletValue::Var(var_name, _, _)) = target {
+                    if self.is_mutable(var_name) == Some(false) {
+                        self.error(
+                            format!("cannot assign to `{}`, it is not declared with `let mut`", var_name),
+                            *span,
+                        );
+                    } else if !self.unify(&value_type, &target_type) {
+                        self.error(
+                            format!(
+                                "cannot assign {} to `{}` of type {}",
+                                self.show(&value_type),
+                                var_name,
+                                self.show(&target_type)
+                            ),
+                            *span,
+                        );
+                    }
+                } else if !self.unify(&value_type, &target_type) {
+                    self.error(
+                        format!(
+                            "cannot assign {} to an element of type {}",
+                            self.show(&value_type),
+                            self.show(&target_type)
+                        ),
+                        *span,
+                    );
+                }
+            }
+            PistoletAST::TypeDef(_, _, _)
+            | PistoletAST::Varbind(_, _, _)
+            | PistoletAST::Paralist(_, _)
+            | PistoletAST::EOI => {}
+        }
+    }
+
+    fn check_loop_body(&mut self, seq: &PistoletAST, label: &Option<String>, scope: Scope) {
+        self.scopes.push(scope);
+        self.loops.push(label.clone());
+        if let PistoletAST::Seq(term_list, _) = seq {
+            self.check_seq(term_list);
+        }
+        self.loops.pop();
+        self.scopes.pop();
+    }
+
+    fn check_jump(&mut self, keyword: &str, label: &Option<String>, span: Span) {
+        match label {
+            _ if self.loops.is_empty() => self.error(format!("`{}` outside a loop", keyword), span),
+            Some(label) if !self.loops.contains(&Some(label.clone())) => {
+                self.error(format!("no loop labeled `'{}`", label), span)
+            }
+            _ => {}
+        }
+    }
+
+    fn mark_mutable(&mut self, pattern: &Pattern) {
+        match pattern {
+            Pattern::Var(var_name, _, _) => {
+                self.scopes.last_mut().unwrap().mut_list.insert(var_name.to_string());
+            }
+            Pattern::Tuple(patterns, _) => patterns.iter().for_each(|p| self.mark_mutable(p)),
+            _ => {}
+        }
+    }
+
+    fn bind_pattern(&mut self, pattern: &Pattern, ty: Type) {
+        match pattern {
+            Pattern::Var(var_name, _, _) => {
+                let scope = self.scopes.last_mut().unwrap();
+                scope.mut_list.remove(&**var_name);
+                scope.var_list.insert(var_name.to_string(), ty);
+            }
+            Pattern::Tuple(patterns, span) => {
+                let items: Vec<Type> = patterns.iter().map(|_| self.fresh(Class::Any)).collect();
+                if !self.unify(&ty, &Type::Tuple(items.clone())) {
+                    self.error(
+                        format!(
+                            "cannot take {} apart into {} names",
+                            self.show(&ty),
+                            patterns.len()
+                        ),
+                        *span,
+                    );
+                }
+                for (pattern, item) in patterns.iter().zip(items) {
+                    self.bind_pattern(pattern, item);
+                }
+            }
+            Pattern::Wildcard(_) => {}
+            Pattern::Literal(value) => {
+                let literal = self.expr_type(&PistoletExpr::Val(value.clone()));
+                if !self.unify(&ty, &literal) {
+                    self.error(
+                        format!(
+                            "pattern is {} but the value matched is {}",
+                            self.show(&literal),
+                            self.show(&ty)
+                        ),
+                        value.span(),
+                    );
+                }
+            }
+            Pattern::Ctor(ctor, arg, span) => {
+                let (type_name, args) = match self.find_ctor(ctor) {
+                    Some(found) => found,
+                    None => {
+                        self.error(format!("unknown constructor `{}`", ctor), *span);
+                        return self.bind_unknown(arg.as_deref());
+                    }
+                };
+                let variant = Type::Named(type_name);
+                if !self.unify(&ty, &variant) {
+                    self.error(
+                        format!(
+                            "constructor `{}` makes {} but the value matched is {}",
+                            ctor,
+                            variant,
+                            self.show(&ty)
+                        ),
+                        *span,
+                    );
+                }
+                match Pattern::ctor_args(arg.as_deref(), args.len()) {
+                    Some(patterns) => {
+                        for (pattern, arg_type) in patterns.iter().zip(args) {
+                            self.bind_pattern(pattern, arg_type);
+                        }
+                    }
+                    None => {
+                        self.error(
+                            format!("constructor `{}` takes {} arguments", ctor, args.len()),
+                            *span,
+                        );
+                        self.bind_unknown(arg.as_deref());
+                    }
+                }
+            }
+        }
+    }
+
+    /* the names in a pattern that could not be checked still get bound, so uses of them are not errors too */
+    fn bind_unknown(&mut self, pattern: Option<&Pattern>) {
+        if let Some(pattern) = pattern {
+            let ty = self.fresh(Class::Any);
+            self.bind_pattern(pattern, ty);
+        }
+    }
+
+    fn expect_type(&mut self, expr: &PistoletExpr, expected: Type, what: &str) {
+        let found = self.expr_type(expr);
+        if !self.unify(&found, &expected) {
+            self.error(
+                format!(
+                    "{} must be {} but is {}",
+                    what,
+                    self.show(&expected),
+                    self.show(&found)
+                ),
+                expr.span(),
+            );
+        }
+    }
+
+    /* an ill-typed expression gets a fresh variable, so one mistake is reported once */
+    fn expr_type(&mut self, expr: &PistoletExpr) -> Type {
+        match expr {
+            PistoletExpr::Val(value) => match value {
+                PistoletValue::Integer(_, _) => Type::Int,
+                PistoletValue::Float(_, _) => Type::Float,
+                PistoletValue::Boolean(_, _) => Type::Bool,
+                PistoletValue::Str(_, _) => Type::Str,
+                PistoletValue::Array(expr_list, _) => {
+                    let elem = self.fresh(Class::Any);
+                    for expr in expr_list {
+                        let expr_type = self.expr_type(expr);
+                        if !self.unify(&expr_type, &elem) {
+                            self.error(
+                                format!(
+                                    "array elements have different types {} and {}",
+                                    self.show(&elem),
+                                    self.show(&expr_type)
+                                ),
+                                expr.span(),
+                            );
+                        }
+                    }
+                    Type::Array(Box::new(elem))
+                }
+                PistoletValue::Tuple(expr_list, _) => {
+                    Type::Tuple(expr_list.iter().map(|expr| self.expr_type(expr)).collect())
+                }
+                PistoletValue::Record(record, fields, span) => {
+                    let declared = match self.find_record(record) {
+                        Some(declared) => declared,
+                        None => {
+                            self.error(format!("unknown type `{}`", record), *span);
+                            for (_, expr) in fields {
+                                self.expr_type(expr);
+                            }
+                            return self.fresh(Class::Any);
+                        }
+                    };
+                    self.check_fields(record, &declared, fields);
+                    for (field, _) in declared.iter() {
+                        if !fields.iter().any(|(name, _)| name == field) {
+                            self.error(
+                                format!("`{}` is missing field `{}`", record, field),
+                                *span,
+                            );
+                        }
+                    }
+                    Type::Named(record.clone())
+                }
+                PistoletValue::Var(var_name, _, span) => match self.find_var(var_name) {
+                    Some(var_type) => var_type.clone(),
+                    None => match self.find_ctor(var_name) {
+                        Some((type_name, args)) => {
+                            if !args.is_empty() {
+                                self.error(
+                                    format!(
+                                        "constructor `{}` takes {} arguments but 0 were given",
+                                        var_name,
+                                        args.len()
+                                    ),
+                                    *span,
+                                );
+                            }
+                            Type::Named(type_name)
+                        }
+                        None => match self.find_func(var_name).cloned() {
+                            /* a named function used as a value */
+                            Some(scheme) => {
+                                let (para_types, return_type) = self.instantiate(&scheme);
+                                Type::Fun(para_types, Box::new(return_type))
+                            }
+                            None => {
+                                self.error(
+                                    format!("var `{}` used before defined", var_name),
+                                    *span,
+                                );
+                                self.fresh(Class::Any)
+                            }
+                        },
+                    },
+                },
+                PistoletValue::Funcall(func_name, expr_list, span) => {
+                    let arg_types: Vec<Type> =
+                        expr_list.iter().map(|expr| self.expr_type(expr)).collect();
+                    if let Some(var_type) = self.find_var(func_name).cloned() {
+                        return self.apply(&var_type, func_name, arg_types, expr_list, *span);
+                    }
+                    let mut kind = "function";
+                    let scheme = match self.find_func(func_name).cloned() {
+                        Some(scheme) => scheme,
+                        None => match self.find_ctor(func_name) {
+                            Some((type_name, args)) => {
+                                kind = "constructor";
+                                Scheme {
+                                    vars: Vec::new(),
+                                    para_names: Vec::new(),
+                                    para_types: args,
+                                    return_type: Type::Named(type_name),
+                                }
+                            }
+                            None => match self.builtin(func_name) {
+                                Some(scheme) => scheme,
+                                None => {
+                                    self.error(
+                                        format!("function `{}` used before defined", func_name),
+                                        *span,
+                                    );
+                                    return self.fresh(Class::Any);
+                                }
+                            },
+                        },
+                    };
+                    let (para_types, return_type) = self.instantiate(&scheme);
+                    self.check_args(kind, func_name, &para_types, arg_types, expr_list, *span);
+                    return_type
+                }
+            },
+            PistoletExpr::Index(e, index, _) => {
+                let ty = self.expr_type(e);
+                let elem = self.fresh(Class::Any);
+                if !self.unify(&ty, &Type::Array(Box::new(elem.clone()))) {
+                    self.error(format!("cannot index into {}", self.show(&ty)), e.span());
+                }
+                self.expect_type(index, Type::Int, "index");
+                elem
+            }
+            PistoletExpr::Field(e, field, span) => {
+                let ty = self.expr_type(e);
+                if let Some((record, fields)) = self.record_of(&ty, field, *span) {
+                    match fields.into_iter().find(|(name, _)| name == field) {
+                        Some((_, field_type)) => return field_type,
+                        None => self.error(format!("`{}` has no field `{}`", record, field), *span),
+                    }
+                }
+                self.fresh(Class::Any)
+            }
+            PistoletExpr::Update(e, fields, _) => {
+                let ty = self.expr_type(e);
+                let (first, _) = &fields[0];
+                match self.record_of(&ty, first, e.span()) {
+                    Some((record, declared)) => {
+                        self.check_fields(&record, &declared, fields);
+                        Type::Named(record)
+                    }
+                    None => {
+                        for (_, expr) in fields {
+                            self.expr_type(expr);
+                        }
+                        self.fresh(Class::Any)
+                    }
+                }
+            }
+            PistoletExpr::Lambda(para_list, return_type, body, span) => {
+                let scheme = self.declare(para_list, return_type, *span);
+                self.check_body(&scheme, body);
+                if !always_returns(body) {
+                    self.error("anonymous function may finish without return".to_string(), *span);
+                }
+                Type::Fun(scheme.para_types, Box::new(scheme.return_type))
+            }
+            PistoletExpr::Call(e, expr_list, span) => {
+                let callee = self.expr_type(e);
+                let arg_types: Vec<Type> =
+                    expr_list.iter().map(|expr| self.expr_type(expr)).collect();
+                self.apply(&callee, &e.to_string(), arg_types, expr_list, *span)
+            }
+            PistoletExpr::Match(e, arms, span) => {
+                let ty = self.expr_type(e);
+                let result = self.fresh(Class::Any);
+                for (pattern, guard, value) in arms {
+                    self.scopes.push(Scope::default());
+                    self.bind_pattern(pattern, ty.clone());
+                    if let Some(guard) = guard {
+                        self.expect_type(guard, Type::Bool, "guard");
+                    }
+                    let value_type = self.expr_type(value);
+                    if !self.unify(&value_type, &result) {
+                        self.error(
+                            format!(
+                                "match arms have different types {} and {}",
+                                self.show(&result),
+                                self.show(&value_type)
+                            ),
+                            value.span(),
+                        );
+                    }
+                    self.scopes.pop();
+                }
+                let arms: Vec<(&Pattern, bool)> = arms
+                    .iter()
+                    .map(|(pattern, guard, _)| (pattern, guard.is_some()))
+                    .collect();
+                self.check_exhaustive(&arms, *span);
+                result
+            }
+            PistoletExpr::Neg(e, span) => {
+                let ty = self.expr_type(e);
+                if !self.require(Class::Numeric, &ty) {
+                    self.error(
+                        format!("operator expects int or float but operand is {}", self.show(&ty)),
+                        *span,
+                    );
+                    return self.fresh(Class::Numeric);
+                }
+                ty
+            }
+            PistoletExpr::Not(e, span) => {
+                let ty = self.expr_type(e);
+                if !self.unify(&ty, &Type::Bool) {
+                    self.error(
+                        format!("operator expects bool but operand is {}", self.show(&ty)),
+                        *span,
+                    );
+                }
+                Type::Bool
+            }
+            PistoletExpr::Add(e1, e2, span) => self.operand_type(e1, e2, Class::Ordered, *span),
+            PistoletExpr::Sub(e1, e2, span)
+            | PistoletExpr::Mul(e1, e2, span)
+            | PistoletExpr::Div(e1, e2, span) => self.operand_type(e1, e2, Class::Numeric, *span),
+            PistoletExpr::And(e1, e2, span)
+            | PistoletExpr::Orb(e1, e2, span)
+            | PistoletExpr::Nand(e1, e2, span) => {
+                let ty = self.operand_type(e1, e2, Class::Any, *span);
+                if !self.unify(&ty, &Type::Bool) {
+                    self.error(
+                        format!("operator expects bool but operands are {}", self.show(&ty)),
+                        *span,
+                    );
+                }
+                Type::Bool
+            }
+            PistoletExpr::Eq(e1, e2, span)
+            | PistoletExpr::Leq(e1, e2, span)
+            | PistoletExpr::Req(e1, e2, span)
+            | PistoletExpr::Left(e1, e2, span)
+            | PistoletExpr::Right(e1, e2, span) => {
+                self.operand_type(e1, e2, Class::Ordered, *span);
+                Type::Bool
+            }
+        }
+    }
+
+    /* the result of calling a function value, `name` is how the callee reads in messages */
+    fn apply(&mut self, callee: &Type, name: &str, arg_types: Vec<Type>, args: &[PistoletExpr], span: Span) -> Type {
+        match self.resolve(callee) {
+            Type::Fun(para_types, return_type) => {
+                self.check_args("function", name, &para_types, arg_types, args, span);
+                *return_type
+            }
+            Type::Var(_) => {
+                let return_type = self.fresh(Class::Any);
+                let fun_type = Type::Fun(arg_types, Box::new(return_type.clone()));
+                if !self.unify(callee, &fun_type) {
+                    self.error(format!("`{}` is {}, not a function", name, self.show(callee)), span);
+                }
+                return_type
+            }
+            other => {
+                self.error(format!("`{}` is {}, not a function", name, self.show(&other)), span);
+                self.fresh(Class::Any)
+            }
+        }
+    }
+
+    fn check_args(
+        &mut self,
+        kind: &str,
+        name: &str,
+        para_types: &[Type],
+        arg_types: Vec<Type>,
+        args: &[PistoletExpr],
+        span: Span,
+    ) {
+        if para_types.len() != arg_types.len() {
+            self.error(
+                format!(
+                    "{} `{}` takes {} arguments but {} were given",
+                    kind,
+                    name,
+                    para_types.len(),
+                    arg_types.len()
+                ),
+                span,
+            );
+        }
+        for ((para_type, arg_type), arg) in para_types.iter().zip(arg_types).zip(args) {
+            if !self.unify(&arg_type, para_type) {
+                self.error(
+                    format!(
+                        "argument of `{}` must be {} but is {}",
+                        name,
+                        self.show(para_type),
+                        self.show(&arg_type)
+                    ),
+                    arg.span(),
+                );
+            }
+        }
+    }
+
+    /* both operands of a binary operator must share one type, which must be in `class` */
+    fn operand_type(&mut self, e1: &PistoletExpr, e2: &PistoletExpr, class: Class, span: Span) -> Type {
+        let t1 = self.expr_type(e1);
+        let t2 = self.expr_type(e2);
+        if !self.unify(&t1, &t2) {
+            self.error(
+                format!(
+                    "operands have different types {} and {}",
+                    self.show(&t1),
+                    self.show(&t2)
+                ),
+                span,
+            );
+            return self.fresh(class);
+        }
+        if !self.require(class, &t1) {
+            self.error(
+                format!("operator expects {} but operands are {}", class, self.show(&t1)),
+                span,
+            );
+            return self.fresh(class);
+        }
+        t1
+    }
+}
+
+impl Default for Checker {
+    fn default() -> Checker {
+        Checker::new()
+    }
+}
+
+pub fn check_program(ast: &PistoletAST) -> Result<(), Vec<TypeErr>> {
+    Checker::new().check(ast)
+}
+
+/* 'a ... 'z, then 'a1, 'b1, ... */
+fn var_name(index: usize) -> String {
+    let letter = (b'a' + (index % 26) as u8) as char;
+    match index / 26 {
+        0 => format!("'{}", letter),
+        round => format!("'{}{}", letter, round),
+    }
+}
+
+fn para_to_vec(para_list: &PistoletAST) -> Vec<(String, Option<Type>, Span)> {
+    match para_list {
+        PistoletAST::Paralist(paralist, _) => paralist
+            .iter()
+            .filter_map(|bind| match bind {
+                PistoletAST::Varbind(v, t, span) => Some((v.to_string(), t.clone(), *span)),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/* whether a `break` or `continue` in `ast` reaches past the loops in it, `inner` holds their labels */
+fn jumps_out(ast: &PistoletAST, inner: &mut Vec<Option<String>>) -> bool {
+    match ast {
+        PistoletAST::Seq(term_list, _) => term_list.iter().any(|term| jumps_out(term, inner)),
+        PistoletAST::Break(label, _) | PistoletAST::Continue(label, _) => match label {
+            None => inner.is_empty(),
+            Some(_) => !inner.contains(label),
+        },
+        PistoletAST::If(_, branch_true, branch_false, _) => {
+            jumps_out(branch_true, inner) || jumps_out(branch_false, inner)
+        }
+        PistoletAST::Match(_, arms, _) => arms.iter().any(|(_, _, body)| jumps_out(body, inner)),
+        PistoletAST::While(seq, _, label, _, _)
+        | PistoletAST::WhileDo(_, seq, label, _)
+        | PistoletAST::For(_, _, _, seq, label, _) => {
+            inner.push(label.clone());
+            let jumps = jumps_out(seq, inner);
+            inner.pop();
+            jumps
+        }
+        _ => false,
+    }
+}
+
+/* the `return`s a block ends with, through the branches of a final `if` or `match` */
+fn tail_returns(term_list: &[PistoletAST], found: &mut HashSet<usize>) {
+    match term_list.iter().rev().find(|term| !matches!(term, PistoletAST::EOI)) {
+        Some(PistoletAST::Return(_, span)) => {
+            found.insert(span.start);
+        }
+        Some(PistoletAST::Seq(term_list, _)) => tail_returns(term_list, found),
+        Some(PistoletAST::If(_, branch_true, branch_false, _)) => {
+            tail_returns(std::slice::from_ref(&**branch_true), found);
+            tail_returns(std::slice::from_ref(&**branch_false), found);
+        }
+        Some(PistoletAST::Match(_, arms, _)) => {
+            for (_, _, body) in arms {
+                tail_returns(std::slice::from_ref(body), found);
+            }
+        }
+        _ => {}
+    }
+}
+
+/* a loop body always runs at least once, so a body that returns makes the loop return */
+fn always_returns(ast: &PistoletAST) -> bool {
+    match ast {
+        PistoletAST::Seq(term_list, _) => term_list.iter().any(always_returns),
+        PistoletAST::Return(_, _) => true,
+        PistoletAST::If(_, branch_true, branch_false, _) => {
+            always_returns(branch_true) && always_returns(branch_false)
+        }
+        /* `loop { ... } ~> false` and `while true { ... }` only end by a jump */
+        PistoletAST::While(seq, cond, _, _, _) => {
+            let forever = matches!(cond, PistoletExpr::Val(PistoletValue::Boolean(false, _)));
+            (forever || always_returns(seq)) && !jumps_out(seq, &mut Vec::new())
+        }
+        PistoletAST::WhileDo(cond, seq, _, _) => {
+            matches!(cond, PistoletExpr::Val(PistoletValue::Boolean(true, _))) && !jumps_out(seq, &mut Vec::new())
+        }
+        /* a value no arm matches stops the program, so it does not fall through */
+        PistoletAST::Match(_, arms, _) => arms.iter().all(|(_, _, body)| always_returns(body)),
+        _ => false,
+    }
+}
@@ -0,0 +1,150 @@
+use crate::ast::*;
+
+/*
+ * Finds a value no arm of a `match` handles, the usefulness check from
+ * Maranget's "Warnings for pattern matching": a row of wildcards is useful
+ * after the arms exactly when some value falls through all of them. Names and
+ * guards are ignored, a guarded arm may not match so it covers nothing.
+ */
+
+/* the constructor list of the variant type a constructor belongs to, with arities */
+pub(crate) type Siblings<'a> = dyn Fn(&str) -> Option<Vec<(String, usize)>> + 'a;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Con {
+    Variant(String),
+    Bool(bool),
+    Tuple(usize),
+    Literal, /* ints, floats and strings, never all listed */
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Any,
+    Con(Con, Vec<Pat>),
+}
+
+/* an example of a value the arms miss, `None` if they cover everything */
+pub(crate) fn missing_case(arms: &[&Pattern], siblings: &Siblings) -> Option<String> {
+    let rows: Vec<Vec<Pat>> = arms.iter().map(|arm| vec![lower(arm, siblings)]).collect();
+    missing(&rows, 1, siblings).map(|witness| show(&witness[0]))
+}
+
+fn lower(pattern: &Pattern, siblings: &Siblings) -> Pat {
+    match pattern {
+        Pattern::Var(_, _, _) | Pattern::Wildcard(_) => Pat::Any,
+        Pattern::Tuple(patterns, _) => Pat::Con(
+            Con::Tuple(patterns.len()),
+            patterns.iter().map(|p| lower(p, siblings)).collect(),
+        ),
+        Pattern::Literal(PistoletValue::Boolean(b, _)) => Pat::Con(Con::Bool(*b), Vec::new()),
+        Pattern::Literal(_) => Pat::Con(Con::Literal, Vec::new()),
+        Pattern::Ctor(name, arg, _) => {
+            let arity = siblings(name)
+                .and_then(|ctors| ctors.into_iter().find(|(ctor, _)| ctor == name))
+                .map(|(_, arity)| arity);
+            /* an unknown constructor is reported by the checker, here it covers nothing */
+            match arity.and_then(|arity| Pattern::ctor_args(arg.as_deref(), arity)) {
+                Some(args) => Pat::Con(
+                    Con::Variant(name.clone()),
+                    args.iter().map(|p| lower(p, siblings)).collect(),
+                ),
+                None => Pat::Con(Con::Literal, Vec::new()),
+            }
+        }
+    }
+}
+
+/* every constructor of the type `con` belongs to, `None` if they cannot all be listed */
+fn signature(con: &Con, siblings: &Siblings) -> Option<Vec<(Con, usize)>> {
+    match con {
+        Con::Variant(name) => siblings(name).map(|ctors| {
+            ctors
+                .into_iter()
+                .map(|(ctor, arity)| (Con::Variant(ctor), arity))
+                .collect()
+        }),
+        Con::Bool(_) => Some(vec![(Con::Bool(true), 0), (Con::Bool(false), 0)]),
+        Con::Tuple(n) => Some(vec![(Con::Tuple(*n), *n)]),
+        Con::Literal => None,
+    }
+}
+
+/* rows whose first pattern can match `con`, with its arguments spread into columns */
+fn specialize(rows: &[Vec<Pat>], con: &Con, arity: usize) -> Vec<Vec<Pat>> {
+    rows.iter()
+        .filter_map(|row| {
+            let mut spread = match &row[0] {
+                Pat::Any => vec![Pat::Any; arity],
+                Pat::Con(head, args) if head == con => args.clone(),
+                Pat::Con(_, _) => return None,
+            };
+            spread.extend_from_slice(&row[1..]);
+            Some(spread)
+        })
+        .collect()
+}
+
+/* a vector of `width` patterns matching values no row matches */
+fn missing(rows: &[Vec<Pat>], width: usize, siblings: &Siblings) -> Option<Vec<Pat>> {
+    if width == 0 {
+        return if rows.is_empty() { Some(Vec::new()) } else { None };
+    }
+    let heads: Vec<&Con> = rows
+        .iter()
+        .filter_map(|row| match &row[0] {
+            Pat::Con(con, _) => Some(con),
+            Pat::Any => None,
+        })
+        .collect();
+    let all = heads.first().and_then(|con| signature(con, siblings));
+    if let Some(all) = &all {
+        if all.iter().all(|(con, _)| heads.contains(&con)) {
+            for (con, arity) in all {
+                let rows = specialize(rows, con, *arity);
+                if let Some(mut witness) = missing(&rows, arity + width - 1, siblings) {
+                    let rest = witness.split_off(*arity);
+                    let mut result = vec![Pat::Con(con.clone(), witness)];
+                    result.extend(rest);
+                    return Some(result);
+                }
+            }
+            return None;
+        }
+    }
+    /* some constructor is not listed, so only the rows starting with a wildcard can catch it */
+    let defaults: Vec<Vec<Pat>> = rows
+        .iter()
+        .filter(|row| matches!(row[0], Pat::Any))
+        .map(|row| row[1..].to_vec())
+        .collect();
+    let rest = missing(&defaults, width - 1, siblings)?;
+    let head = all
+        .and_then(|all| all.into_iter().find(|(con, _)| !heads.contains(&con)))
+        .map_or(Pat::Any, |(con, arity)| Pat::Con(con, vec![Pat::Any; arity]));
+    let mut result = vec![head];
+    result.extend(rest);
+    Some(result)
+}
+
+fn show(pat: &Pat) -> String {
+    match pat {
+        Pat::Any | Pat::Con(Con::Literal, _) => "_".to_string(),
+        Pat::Con(Con::Bool(b), _) => b.to_string(),
+        Pat::Con(Con::Tuple(_), args) => {
+            let items: Vec<String> = args.iter().map(show).collect();
+            format!("({})", items.join(", "))
+        }
+        Pat::Con(Con::Variant(name), args) => match args.as_slice() {
+            [] => name.clone(),
+            [arg @ Pat::Con(Con::Variant(_), inner)] if !inner.is_empty() => {
+                format!("{} ({})", name, show(arg))
+            }
+            [arg] => format!("{} {}", name, show(arg)),
+            args => {
+                let items: Vec<String> = args.iter().map(show).collect();
+                format!("{} ({})", name, items.join(", "))
+            }
+        },
+    }
+}
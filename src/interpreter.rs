@@ -1,3 +1,6 @@
+use crate::ast::*;
+use crate::checker::{Checker, TypeErr, TypeWarning};
+use crate::resolver::Resolver;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::VecDeque;
@@ -5,70 +8,139 @@ use std::fmt;
 use std::rc::Rc;
 
 #[derive(Debug, Clone)]
-enum ValueBind {
-    Vb(String, VarValue), /* type, value*/
+pub enum ValueBind {
+    Vb(Type, VarValue), /* type, value*/
 }
 
 impl ValueBind {
-    pub fn get_type(&self) -> String {
+    pub fn get_type(&self) -> Type {
         match self {
-            ValueBind::Vb(t, _) => t.to_string(),
+            /* an array's element type can be learned after it is bound, by `push` */
+            ValueBind::Vb(_, v @ VarValue::Array(_)) => v.get_type(),
+            ValueBind::Vb(t, _) => t.clone(),
         }
     }
     pub fn get_value(&self) -> VarValue {
         match self {
-            ValueBind::Vb(_, v) => *v,
+            ValueBind::Vb(_, v) => v.clone(),
         }
     }
 }
 
+/* a function as it was written, shared by every closure made from it so a call copies no syntax */
+#[derive(Debug)]
+pub(crate) struct FunDef {
+    name: String,
+    para_list: Vec<(Rc<str>, Option<Type>)>,
+    return_type: Option<Type>,
+    body: Rc<PistoletAST>,
+}
+
+impl FunDef {
+    fn new(name: String, para_list: &PistoletAST, return_type: Option<Type>, body: Rc<PistoletAST>) -> FunDef {
+        let para_list = match para_list {
+            PistoletAST::Paralist(paralist, _) => para_to_vec(paralist),
+            _ => Vec::new(),
+        };
+        FunDef {
+            name,
+            para_list,
+            return_type,
+            body,
+        }
+    }
+}
+
+/* the definition and the bindings visible where the function was defined */
+type FuncEntry = (Rc<FunDef>, ProgStates);
+
+/* the functions one block defines, shared so they can call each other whatever their order */
+#[derive(Debug)]
+struct FuncScope {
+    func_list: RefCell<HashMap<String, FuncEntry>>,
+    outer: Option<Rc<FuncScope>>, /* the enclosing block */
+}
+
 #[derive(Debug, Clone)]
-struct FuncDic {
-    func_list: HashMap<String, (PistoletAST, String, PistoletAST)>
+pub struct FuncDic {
+    scope: Rc<FuncScope>, /* the innermost block */
+    ctor_list: Rc<HashMap<String, (String, usize)>>, /* constructor: its type and arity, copied on write */
 }
 
 impl FuncDic {
     pub fn new() -> FuncDic {
-        let func_list = FuncDic{
-            func_list: HashMap::new()
-        };
-        return func_list;
-    }
-
-    pub fn find_func(
-        &self,
-        name: String,
-    ) -> Result<(PistoletAST, String, Vec<(String, String)>), RuntimeErr> {
-        let fun = self.func_list.get(&name);
-            match fun {
-                Some(result) => {
-                    match result {
-                        (PistoletAST::Paralist(paralist), func_type, func_body) => {
-                            let para_vec = para_to_vec(paralist.clone());
-                            Ok((func_body.clone(), func_type.clone(), para_vec))
-                        }
-                        _ => unreachable!(),
-                    }
-                }
-                None => Err(RuntimeErr::FuncUsedBeforeDefine)
+        FuncDic {
+            scope: Rc::new(FuncScope {
+                func_list: RefCell::new(HashMap::new()),
+                outer: None,
+            }),
+            ctor_list: Rc::new(HashMap::new()),
+        }
+    }
+
+    /* the functions seen inside a block, its own definitions go to a new scope */
+    fn enter(&self) -> FuncDic {
+        FuncDic {
+            scope: Rc::new(FuncScope {
+                func_list: RefCell::new(HashMap::new()),
+                outer: Some(self.scope.clone()),
+            }),
+            ctor_list: self.ctor_list.clone(),
+        }
+    }
+
+    pub fn find_ctor(&self, name: &str) -> Option<(String, usize)> {
+        self.ctor_list.get(name).cloned()
+    }
+
+    pub fn ctor_insert(&mut self, ctor: String, type_name: String, arity: usize) {
+        Rc::make_mut(&mut self.ctor_list).insert(ctor, (type_name, arity));
+    }
+
+    /* the function with its definition environment, the functions it sees start at the block defining it */
+    pub fn find_func(&self, name: &str, span: Span) -> Result<Closure, RuntimeErr> {
+        let mut scope = Some(&self.scope);
+        while let Some(current) = scope {
+            if let Some((def, env)) = current.func_list.borrow().get(name) {
+                return Ok(Closure {
+                    def: def.clone(),
+                    env: env.clone(),
+                    funcs: FuncDic {
+                        scope: current.clone(),
+                        ctor_list: self.ctor_list.clone(),
+                    },
+                });
             }
+            scope = current.outer.as_ref();
+        }
+        Err(RuntimeErr::FuncUsedBeforeDefine(name.to_string(), span))
     }
 
     pub fn func_insert(
         &mut self,
         func_name: String,
-        para_list: PistoletAST,
-        return_type: String,
-        func_body: PistoletAST,
+        para_list: &PistoletAST,
+        return_type: Option<Type>,
+        func_body: Rc<PistoletAST>,
+        env: ProgStates,
     ) {
-        self.func_list
-            .insert(func_name, (para_list, return_type, func_body));
+        let def = FunDef::new(func_name.clone(), para_list, return_type, func_body);
+        self.scope.func_list.borrow_mut().insert(func_name, (Rc::new(def), env));
     }
 }
 
+impl Default for FuncDic {
+    fn default() -> FuncDic {
+        FuncDic::new()
+    }
+}
+
+/* each binding lives in its own cell, so a closure can share it without seeing later `let`s */
+type VarCell = Rc<RefCell<(Rc<str>, ValueBind, bool)>>; /* name, value, mutable */
+
 #[derive(Debug)]
 struct ProgList {
-    var_list: HashMap<String, ValueBind>
+    var_list: Rc<Vec<Option<VarCell>>>, /* by the index the resolver gave each name, shared with the closures that captured it */
 }
 
 #[derive(Debug)]
@@ -77,157 +149,598 @@ struct ProgState(Rc<RefCell<ProgList>>);
 #[derive(Debug)]
 struct StateVec {
     states: VecDeque<ProgState>,
+    depth: usize,     /* function calls active */
+    max_depth: usize, /* calls that may be active at once */
 }
 
+/* calls active at once before a program stops with a stack overflow */
+pub const DEFAULT_MAX_DEPTH: usize = 10_000;
+/* when less than this is left of the native stack a call continues on a new piece of this size */
+const RED_ZONE: usize = 256 * 1024;
+const STACK_GROWTH: usize = 8 * 1024 * 1024;
+
 #[derive(Debug, Clone)]
-struct ProgStates(Rc<RefCell<StateVec>>);
+pub struct ProgStates(Rc<RefCell<StateVec>>);
 
 impl ProgStates {
     pub fn new() -> ProgStates {
         let main_state = ProgState(Rc::new(RefCell::new(ProgList {
-            var_list: HashMap::new()
+            var_list: Rc::default()
         })));
 
         let state = ProgStates(Rc::new(RefCell::new(StateVec {
             states: VecDeque::new(),
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
         })));
 
         state.push_back(main_state);
-        return state;
+        state
     }
-    pub fn push_front(&self, state: ProgState) {
+    fn push_front(&self, state: ProgState) {
         self.0.borrow_mut().states.push_front(state)
     }
-    pub fn push_back(&self, state: ProgState) {
+    fn push_back(&self, state: ProgState) {
         self.0.borrow_mut().states.push_back(state)
     }
-    pub fn pop_front(&self) {
+    fn pop_front(&self) {
         self.0.borrow_mut().states.pop_front();
     }
-    pub fn find_var(&self, name: String) -> Result<ValueBind, RuntimeErr> {
-        let mut r: ValueBind = ValueBind::Vb("foo".to_string(), VarValue::Bool(true)); // dummy useless initial value
-        let mut find_var: bool = false;
-        for state in self.0.borrow().states.iter() {
-            match state.get(name.clone()) {
-                Some(result) => {
-                    r = result.clone();
-                    find_var = true;
-                    break;
-                }
-                None => continue,
-            }
+    /* the innermost binding of `name`, by searching every frame */
+    pub fn find_var(&self, name: &str, span: Span) -> Result<ValueBind, RuntimeErr> {
+        let found = self.0.borrow().states.iter().find_map(|state| state.find(name));
+        found.ok_or_else(|| RuntimeErr::VarUsedBeforeDefine(name.to_string(), span))
+    }
+    fn cell(&self, slot: Slot) -> Option<VarCell> {
+        let states = self.0.borrow();
+        let state = states.states.get(slot.depth)?.0.borrow();
+        state.var_list.get(slot.index)?.clone()
+    }
+    fn get(&self, slot: Slot) -> Option<ValueBind> {
+        self.cell(slot).map(|cell| cell.borrow().1.clone())
+    }
+    /* binds `index` of the innermost frame to a new cell */
+    fn bind(&self, index: usize, var_name: &Rc<str>, var_value: ValueBind, mutable: bool) {
+        let cell = Rc::new(RefCell::new((var_name.clone(), var_value, mutable)));
+        self.0.borrow().states.front().unwrap().set(index, cell);
+    }
+    /* updates the binding `slot` refers to, in whichever frame it was made */
+    fn assign(&self, name: &str, slot: Slot, var_value: ValueBind, span: Span) -> Result<(), RuntimeErr> {
+        let cell = self
+            .cell(slot)
+            .ok_or_else(|| RuntimeErr::VarUsedBeforeDefine(name.to_string(), span))?;
+        let mut binding = cell.borrow_mut();
+        let (_, old_value, mutable) = &*binding;
+        if !*mutable {
+            return Err(RuntimeErr::AssignImmutable(name.to_string(), span));
+        }
+        if !type_dec(&old_value.get_value(), &var_value.get_value()) {
+            return Err(RuntimeErr::TypeMismatch(
+                old_value.get_type().to_string(),
+                var_value.get_type().to_string(),
+                span,
+            ));
+        }
+        binding.1 = var_value;
+        Ok(())
+    }
+    pub fn print(&self) {
+        self.0.borrow_mut().states.front().unwrap().print();
+    }
+    /* a new stack over the same frames, what is pushed onto it stays out of this one */
+    fn branch(&self) -> ProgStates {
+        let state_vec = self.0.borrow();
+        let states = state_vec.states.iter().map(|state| ProgState(state.0.clone())).collect();
+        ProgStates(Rc::new(RefCell::new(StateVec {
+            states,
+            depth: state_vec.depth,
+            max_depth: state_vec.max_depth,
+        })))
+    }
+    /* the environment of a function called from `caller`, one call deeper than it */
+    fn call_from(&self, caller: &ProgStates, span: Span) -> Result<ProgStates, RuntimeErr> {
+        let (depth, max_depth) = {
+            let caller = caller.0.borrow();
+            (caller.depth, caller.max_depth)
+        };
+        if depth >= max_depth {
+            return Err(RuntimeErr::StackOverflow(max_depth, span));
         }
-        if find_var {
-            Ok(r)
-        } else {
-            Err(RuntimeErr::VarUsedBeforeDefine)
+        let states = self.branch();
+        {
+            let mut state_vec = states.0.borrow_mut();
+            state_vec.depth = depth + 1;
+            state_vec.max_depth = max_depth;
         }
+        Ok(states)
     }
-    pub fn insert(&self, var_name: String, var_value: ValueBind) {
-        self.0
-            .borrow_mut()
+    fn set_max_depth(&self, max_depth: usize) {
+        self.0.borrow_mut().max_depth = max_depth;
+    }
+    /* the bindings visible here, frame by frame, as a closure keeps them: a frame binding a name again copies its list then */
+    fn capture(&self) -> ProgStates {
+        let state_vec = self.0.borrow();
+        let states = state_vec
             .states
-            .get(0)
-            .unwrap()
-            .insert(var_name, var_value);
+            .iter()
+            .map(|state| {
+                let var_list = Rc::clone(&state.0.borrow().var_list);
+                ProgState(Rc::new(RefCell::new(ProgList { var_list })))
+            })
+            .collect();
+        ProgStates(Rc::new(RefCell::new(StateVec {
+            states,
+            depth: state_vec.depth,
+            max_depth: state_vec.max_depth,
+        })))
     }
-    pub fn print(&self) {
-        self.0.borrow_mut().states.get(0).unwrap().print();
+}
+
+impl Default for ProgStates {
+    fn default() -> ProgStates {
+        ProgStates::new()
     }
 }
 
 impl ProgState {
-    pub fn insert(&self, var_name: String, var_value: ValueBind) {
-        self.0.borrow_mut().var_list.insert(var_name, var_value);
-    }
-    pub fn get(&self, var_name: String) -> Option<ValueBind> {
-        match self.0.borrow().var_list.get(&var_name) {
-            Some(n) => Some(n.clone()),
-            None => None,
+    fn set(&self, index: usize, cell: VarCell) {
+        let mut state = self.0.borrow_mut();
+        let var_list = Rc::make_mut(&mut state.var_list);
+        if var_list.len() <= index {
+            var_list.resize(index + 1, None);
         }
+        var_list[index] = Some(cell);
+    }
+    fn find(&self, var_name: &str) -> Option<ValueBind> {
+        let state = self.0.borrow();
+        let mut cells = state.var_list.iter().flatten();
+        cells.find(|cell| &*cell.borrow().0 == var_name).map(|cell| cell.borrow().1.clone())
     }
     pub fn print(&self) {
         println!("------ PROGRAM STATE ------");
-        for var in &(self.0.borrow().var_list) {
-            let (var_name, ValueBind::Vb(type_name, var_value)) = var;
+        for cell in self.0.borrow().var_list.iter().flatten() {
+            let (var_name, var_value, _) = &*cell.borrow();
             println!(
                 "Var: {}    Type: {}    Value: {}",
-                var_name, type_name, var_value
+                var_name,
+                var_value.get_type(),
+                var_value.get_value()
             )
         }
         println!("------ PROGRAM STATE ------");
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-enum VarValue {
+#[derive(Debug, Clone)]
+pub enum VarValue {
     Int(i128),
     Float(f64),
     Bool(bool),
+    Str(String),
+    Array(ArrayRef),
+    Tuple(Parts),
+    Record(String, Fields),               /* type, fields */
+    Variant(String, String, Parts),       /* type, constructor, arguments */
+    Func(Rc<Closure>),
 }
 
-impl fmt::Display for VarValue {
+/* a function value: a lambda, or a named function used without calling it */
+#[derive(Clone)]
+pub struct Closure {
+    def: Rc<FunDef>,
+    env: ProgStates, /* the bindings visible where it was made */
+    funcs: FuncDic,  /* and the functions */
+}
+
+impl Closure {
+    pub fn get_type(&self) -> Type {
+        let para_types = self
+            .def
+            .para_list
+            .iter()
+            .map(|(_, para_type)| para_type.clone().unwrap_or(Type::Var(0)))
+            .collect();
+        let return_type = self.def.return_type.clone().unwrap_or(Type::Var(0));
+        Type::Fun(para_types, Box::new(return_type))
+    }
+}
+
+/* the environment may hold the closure itself, so it is left out */
+impl fmt::Debug for Closure {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Closure({}: {})", self.def.name, self.get_type())
+    }
+}
+
+/* arrays are shared, so changes are seen through every binding */
+pub type ArrayRef = Rc<RefCell<Vec<VarValue>>>;
+
+/* the parts of a tuple or a variant, which never change, so copies of the value share them */
+#[derive(Debug, Clone)]
+pub struct Parts(Rc<Vec<VarValue>>);
+
+/* the fields of a record, shared the same way */
+#[derive(Debug, Clone)]
+pub struct Fields(Rc<Vec<(String, VarValue)>>);
+
+impl Parts {
+    pub fn new(items: Vec<VarValue>) -> Parts {
+        Parts(Rc::new(items))
+    }
+}
+
+impl Fields {
+    pub fn new(fields: Vec<(String, VarValue)>) -> Fields {
+        Fields(Rc::new(fields))
+    }
+}
+
+impl std::ops::Deref for Parts {
+    type Target = [VarValue];
+    fn deref(&self) -> &[VarValue] {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for Fields {
+    type Target = [(String, VarValue)];
+    fn deref(&self) -> &[(String, VarValue)] {
+        &self.0
+    }
+}
+
+/* a list built of variants may be a million values deep, too deep to drop recursively */
+impl Drop for Parts {
+    fn drop(&mut self) {
+        if let Some(items) = Rc::get_mut(&mut self.0) {
+            drop_parts(std::mem::take(items));
+        }
+    }
+}
+
+impl Drop for Fields {
+    fn drop(&mut self) {
+        if let Some(fields) = Rc::get_mut(&mut self.0) {
+            drop_parts(fields.drain(..).map(|(_, value)| value).collect());
+        }
+    }
+}
+
+/* empties the parts no other value shares before dropping each value, so none of them has any left to drop */
+fn drop_parts(mut pending: Vec<VarValue>) {
+    while let Some(mut value) = pending.pop() {
+        match &mut value {
+            VarValue::Tuple(items) | VarValue::Variant(_, _, items) => {
+                if let Some(items) = Rc::get_mut(&mut items.0) {
+                    pending.append(items);
+                }
+            }
+            VarValue::Record(_, fields) => {
+                if let Some(fields) = Rc::get_mut(&mut fields.0) {
+                    pending.extend(fields.drain(..).map(|(_, value)| value));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl VarValue {
+    pub fn get_type(&self) -> Type {
         match self {
-            VarValue::Int(i) => write!(f, "{}", i),
-            VarValue::Float(i) => write!(f, "{}", i),
-            VarValue::Bool(i) => write!(f, "{}", i),
+            VarValue::Int(_) => Type::Int,
+            VarValue::Float(_) => Type::Float,
+            VarValue::Bool(_) => Type::Bool,
+            VarValue::Str(_) => Type::Str,
+            VarValue::Array(items) => Type::Array(Box::new(
+                items.borrow().first().map_or(Type::Var(0), VarValue::get_type),
+            )),
+            VarValue::Tuple(items) => Type::Tuple(items.iter().map(VarValue::get_type).collect()),
+            VarValue::Record(name, _) | VarValue::Variant(name, _, _) => Type::Named(name.clone()),
+            VarValue::Func(closure) => closure.get_type(),
         }
     }
 }
 
+/* what is left to print of a value, in reverse order */
+enum Piece {
+    Value(VarValue),
+    Text(&'static str),
+    Field(String), /* the `name = ` before a field value */
+}
+
+impl fmt::Display for VarValue {
+    /* by a worklist rather than recursion, a list nests as deep as it is long */
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pending = vec![Piece::Value(self.clone())];
+        while let Some(piece) = pending.pop() {
+            let value = match piece {
+                Piece::Value(value) => value,
+                Piece::Text(text) => {
+                    f.write_str(text)?;
+                    continue;
+                }
+                Piece::Field(field) => {
+                    write!(f, "{} = ", field)?;
+                    continue;
+                }
+            };
+            let (open, close, items) = match &value {
+                VarValue::Int(i) => {
+                    write!(f, "{}", i)?;
+                    continue;
+                }
+                VarValue::Float(i) => {
+                    write!(f, "{}", i)?;
+                    continue;
+                }
+                VarValue::Bool(i) => {
+                    write!(f, "{}", i)?;
+                    continue;
+                }
+                VarValue::Str(i) => {
+                    write!(f, "{:?}", i)?;
+                    continue;
+                }
+                VarValue::Func(_) => {
+                    f.write_str("<fun>")?;
+                    continue;
+                }
+                VarValue::Variant(_, ctor, args) if args.is_empty() => {
+                    f.write_str(ctor)?;
+                    continue;
+                }
+                VarValue::Record(name, fields) => {
+                    write!(f, "{} {{ ", name)?;
+                    pending.push(Piece::Text(" }"));
+                    for (index, (field, value)) in fields.iter().enumerate().rev() {
+                        pending.push(Piece::Value(value.clone()));
+                        pending.push(Piece::Field(field.clone()));
+                        if index > 0 {
+                            pending.push(Piece::Text(", "));
+                        }
+                    }
+                    continue;
+                }
+                VarValue::Array(items) => ("[", "]", items.borrow().clone()),
+                VarValue::Tuple(items) => ("(", ")", items.to_vec()),
+                VarValue::Variant(_, ctor, args) => {
+                    f.write_str(ctor)?;
+                    ("(", ")", args.to_vec())
+                }
+            };
+            f.write_str(open)?;
+            pending.push(Piece::Text(close));
+            for (index, item) in items.into_iter().enumerate().rev() {
+                pending.push(Piece::Value(item));
+                if index > 0 {
+                    pending.push(Piece::Text(", "));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/* how a statement finished when it did not fail */
+#[derive(Debug)]
+pub(crate) enum Flow {
+    Normal,
+    Return(ValueBind, Span),
+    TailCall(Rc<Closure>, Vec<(ValueBind, Span)>, Span), /* function, arguments, call site */
+    Break(Option<String>, Span),    /* label */
+    Continue(Option<String>, Span), /* label */
+}
+
 #[derive(Debug)]
-enum RuntimeErr {
-    TypeMismatch,
-    Unknown,
-    VarUsedBeforeDefine,
-    FuncUsedBeforeDefine,
-    DivideByZero,
-    FuncallParaNum,
-    FunctionNoReturn,
-    ReturnValue(ValueBind),
+pub enum RuntimeErr {
+    TypeMismatch(String, String, Span), /* expected type, found type */
+    Unknown(Span),
+    VarUsedBeforeDefine(String, Span),
+    FuncUsedBeforeDefine(String, Span),
+    DivideByZero(Span),
+    IndexOutOfBounds(i128, usize, Span), /* index, length */
+    NoField(String, String, Span),       /* type, field */
+    MatchFailure(String, Span),          /* the value no arm matched */
+    FuncallParaNum(String, usize, usize, Span), /* function, expected, found */
+    ParaTypeMismatch(Rc<str>, Rc<str>, String, String, Span), /* function, parameter, expected type, found type */
+    FunctionNoReturn(String, Span),
+    AssignImmutable(String, Span),
+    StackOverflow(usize, Span), /* the most calls allowed at once, call site */
+    Overflow(Span),             /* an int result out of range */
+    Break(Option<String>, Span),       /* no loop took it, label */
+    Continue(Option<String>, Span),    /* no loop took it, label */
+    InFunction(Box<RuntimeErr>, Vec<(String, Span)>), /* error, function and call site of each frame, innermost first */
 }
 
+const BACKTRACE_EDGE: usize = 10; /* frames kept at each end of a long backtrace */
+
 impl RuntimeErr {
+    /* the error that was raised, below any function frames it unwound through */
+    pub fn root(&self) -> &RuntimeErr {
+        match self {
+            RuntimeErr::InFunction(err, _) => err,
+            _ => self,
+        }
+    }
+    /* active function calls at the point of failure, innermost first */
+    pub fn backtrace(&self) -> Vec<(String, Span)> {
+        match self {
+            RuntimeErr::InFunction(_, frames) => frames.clone(),
+            _ => Vec::new(),
+        }
+    }
+    /* the error as it leaves a call, the frames stay in one list however deep the recursion was */
+    fn in_function(self, func_name: &str, call_site: Span) -> RuntimeErr {
+        match self {
+            RuntimeErr::InFunction(err, mut frames) => {
+                frames.push((func_name.to_string(), call_site));
+                RuntimeErr::InFunction(err, frames)
+            }
+            err => RuntimeErr::InFunction(Box::new(err), vec![(func_name.to_string(), call_site)]),
+        }
+    }
+    pub fn print(&self) {
+        eprintln!("------ Runtime Error ------");
+        eprintln!("[Error] {}", self);
+        let frames = self.backtrace();
+        for (index, (func_name, call_site)) in frames.iter().enumerate() {
+            if frames.len() > 2 * BACKTRACE_EDGE
+                && index >= BACKTRACE_EDGE
+                && index < frames.len() - BACKTRACE_EDGE
+            {
+                if index == BACKTRACE_EDGE {
+                    eprintln!(
+                        "    ... {} more frames ...",
+                        frames.len() - 2 * BACKTRACE_EDGE
+                    );
+                }
+                continue;
+            }
+            eprintln!("    in {} (called at {})", func_name, call_site);
+        }
+        eprintln!("------ Runtime Error ------");
+    }
+}
+
+impl fmt::Display for RuntimeErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.root() {
+            RuntimeErr::TypeMismatch(expected, found, span) => write!(
+                f,
+                "{}: type mismatch, expected {} but found {}",
+                span, expected, found
+            ),
+            RuntimeErr::VarUsedBeforeDefine(var_name, span) => {
+                write!(f, "{}: var `{}` used before defined", span, var_name)
+            }
+            RuntimeErr::Unknown(span) => write!(f, "{}: an exception has occurred", span),
+            RuntimeErr::DivideByZero(span) => write!(f, "{}: attempt to divide by zero", span),
+            RuntimeErr::IndexOutOfBounds(index, len, span) => write!(
+                f,
+                "{}: index {} is out of bounds for an array of length {}",
+                span, index, len
+            ),
+            RuntimeErr::NoField(type_name, field, span) => {
+                write!(f, "{}: `{}` has no field `{}`", span, type_name, field)
+            }
+            RuntimeErr::MatchFailure(value, span) => {
+                write!(f, "{}: no pattern matches {}", span, value)
+            }
+            RuntimeErr::FuncUsedBeforeDefine(func_name, span) => {
+                write!(f, "{}: function `{}` used before defined", span, func_name)
+            }
+            RuntimeErr::FuncallParaNum(func_name, expected, found, span) => write!(
+                f,
+                "{}: function `{}` takes {} arguments but {} were given",
+                span, func_name, expected, found
+            ),
+            RuntimeErr::ParaTypeMismatch(func_name, para_name, expected, found, span) => write!(
+                f,
+                "{}: parameter `{}` of function `{}` expects {} but found {}",
+                span, para_name, func_name, expected, found
+            ),
+            RuntimeErr::FunctionNoReturn(func_name, span) => {
+                write!(f, "{}: function `{}` finished without return", span, func_name)
+            }
+            RuntimeErr::AssignImmutable(var_name, span) => write!(
+                f,
+                "{}: cannot assign to `{}`, it is not declared with `let mut`",
+                span, var_name
+            ),
+            RuntimeErr::Overflow(span) => write!(f, "{}: integer overflow", span),
+            RuntimeErr::StackOverflow(max_depth, span) => write!(
+                f,
+                "{}: stack overflow, more than {} function calls active at once",
+                span, max_depth
+            ),
+            RuntimeErr::Break(None, span) => write!(f, "{}: `break` outside a loop", span),
+            RuntimeErr::Continue(None, span) => write!(f, "{}: `continue` outside a loop", span),
+            RuntimeErr::Break(Some(label), span) | RuntimeErr::Continue(Some(label), span) => {
+                write!(f, "{}: no loop labeled `'{}`", span, label)
+            }
+            RuntimeErr::InFunction(_, _) => unreachable!(),
+        }
+    }
+}
+
+/* why `run` or `eval_expr` stopped: names never defined, found before anything ran, or an error while running */
+#[derive(Debug)]
+pub enum RunErr {
+    Unresolved(Vec<TypeErr>),
+    Runtime(RuntimeErr),
+}
+
+impl RunErr {
     pub fn print(&self) {
-        println!("------ Runtime Error ------");
         match self {
-            RuntimeErr::TypeMismatch => println!("[Error] Type mismatch in an expression"),
-            RuntimeErr::VarUsedBeforeDefine => println!("[Error] Var used before defined"),
-            RuntimeErr::Unknown => println!("[Error] An exception has occurred"),
-            RuntimeErr::DivideByZero => println!("[Error] Attempt to divide by zero "),
-            RuntimeErr::FuncUsedBeforeDefine => println!("[Error] Function used before defined"),
-            RuntimeErr::FuncallParaNum => println!("[Error] wrong number function call"),
-            RuntimeErr::FunctionNoReturn => println!("[Error] function no return"),
-            _ => unreachable!(),
+            RunErr::Unresolved(errors) => {
+                for err in errors {
+                    eprintln!("{}", err);
+                }
+            }
+            RunErr::Runtime(err) => err.print(),
+        }
+    }
+}
+
+impl From<RuntimeErr> for RunErr {
+    fn from(err: RuntimeErr) -> RunErr {
+        RunErr::Runtime(err)
+    }
+}
+
+impl fmt::Display for RunErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunErr::Unresolved(errors) => {
+                let errors: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            RunErr::Runtime(err) => write!(f, "{}", err),
         }
-        println!("------ Runtime Error ------");
     }
 }
 
-fn type_dec(v1: VarValue, v2: VarValue) -> bool {
+fn type_dec(v1: &VarValue, v2: &VarValue) -> bool {
     match v1 {
-        VarValue::Int(_) => match v2 {
-            VarValue::Int(_) => true,
-            _ => false,
-        },
-        VarValue::Float(_) => match v2 {
-            VarValue::Float(_) => true,
-            _ => false,
-        },
-        VarValue::Bool(_) => match v2 {
-            VarValue::Bool(_) => true,
-            _ => false,
-        },
+        VarValue::Int(_) => matches!(v2, VarValue::Int(_)),
+        VarValue::Float(_) => matches!(v2, VarValue::Float(_)),
+        VarValue::Bool(_) => matches!(v2, VarValue::Bool(_)),
+        VarValue::Str(_) => matches!(v2, VarValue::Str(_)),
+        VarValue::Array(_) => matches!(v2, VarValue::Array(_)),
+        VarValue::Tuple(_) => matches!(v2, VarValue::Tuple(_)),
+        VarValue::Record(n1, _) => matches!(v2, VarValue::Record(n2, _) if n1 == n2),
+        VarValue::Variant(n1, _, _) => matches!(v2, VarValue::Variant(n2, _, _) if n1 == n2),
+        VarValue::Func(_) => matches!(v2, VarValue::Func(_)),
+    }
+}
+
+/* whether a value may be bound where `ty` is declared, an empty array fits any array type */
+fn fits(ty: &Type, value: &VarValue) -> bool {
+    stacker::maybe_grow(RED_ZONE, STACK_GROWTH, || fits_now(ty, value))
+}
+
+fn fits_now(ty: &Type, value: &VarValue) -> bool {
+    match (ty, value) {
+        (Type::Array(elem), VarValue::Array(items)) => {
+            items.borrow().iter().all(|item| fits(elem, item))
+        }
+        (Type::Tuple(types), VarValue::Tuple(items)) => {
+            types.len() == items.len() && types.iter().zip(items.iter()).all(|(ty, item)| fits(ty, item))
+        }
+        /* what a function does with its arguments is only known once it is called */
+        (Type::Fun(para_types, _), VarValue::Func(closure)) => para_types.len() == closure.def.para_list.len(),
+        (ty, value) => *ty == value.get_type(),
     }
 }
 
-fn para_to_vec(paralist: Vec<PistoletAST>) -> Vec<(String, String)> {
-    let mut result: Vec<(String, String)> = Vec::new();
+fn para_to_vec(paralist: &[PistoletAST]) -> Vec<(Rc<str>, Option<Type>)> {
+    let mut result: Vec<(Rc<str>, Option<Type>)> = Vec::new();
     for bind in paralist.iter() {
         match bind {
-            PistoletAST::Varbind(v, t) => {
-                result.push((v.to_string(), t.to_string()));
+            PistoletAST::Varbind(v, t, _) => {
+                result.push((v.clone(), t.clone()));
             }
             _ => unreachable!(),
         }
@@ -235,488 +748,1063 @@ fn para_to_vec(paralist: Vec<PistoletAST>) -> Vec<(String, String)> {
     result
 }
 
-fn var_eval(name: String, states: ProgStates) -> Result<ValueBind, RuntimeErr> {
-    states.find_var(name)
-}
-
 fn func_eval(
-    name: String,
-    expr_list: Vec<PistoletExpr>,
-    states: ProgStates,
-    func_list: FuncDic
-    
+    name: &str,
+    expr_list: &[PistoletExpr],
+    span: Span,
+    states: &ProgStates,
+    func_list: &FuncDic
 ) -> Result<ValueBind, RuntimeErr> {
-    let (func_body, func_type, para_list) = func_list.find_func(name)?;
-    let mut val_list: Vec<ValueBind> = Vec::new();
+    /* calls of variables holding functions were made `Call`s by the resolver */
+    match func_list.find_func(name, span) {
+        Ok(closure) => closure_eval(&closure, expr_list, span, states, func_list),
+        Err(_) => match func_list.find_ctor(name) {
+            Some((type_name, arity)) => ctor_eval(name, type_name, arity, expr_list, span, states, func_list),
+            None => builtin_eval(name, expr_list, span, states, func_list),
+        },
+    }
+}
+
+fn args_eval(
+    expr_list: &[PistoletExpr],
+    states: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Vec<(ValueBind, Span)>, RuntimeErr> {
+    let mut val_list: Vec<(ValueBind, Span)> = Vec::new();
     for expr in expr_list.iter() {
-        let expr_val = expr_eval(expr.clone(), states.clone(), func_list.clone()).unwrap();
-        val_list.push(expr_val);
+        let expr_val = expr_eval(expr, states, func_list)?;
+        val_list.push((expr_val, expr.span()));
     }
-    if val_list.len() == para_list.len() {
-        let sub_state = ProgState(Rc::new(RefCell::new(ProgList {
-            var_list: HashMap::new()
-        })));
-        states.push_front(sub_state);
-        for (index, val) in val_list.iter().enumerate() {
-            let (para_name, para_type) = para_list.get(index).unwrap();
-            if val.get_type().eq_ignore_ascii_case(&para_type) {
-                states.insert(para_name.clone(), val.clone());
-            } else {
-                states.pop_front();
-                return Err(RuntimeErr::TypeMismatch);
+    Ok(val_list)
+}
+
+fn closure_eval(
+    closure: &Closure,
+    expr_list: &[PistoletExpr],
+    span: Span,
+    states: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let val_list = args_eval(expr_list, states, func_list)?;
+    apply(closure, val_list, span, states)
+}
+
+/*
+ * Calls a function. A call the body returns is made here once the body has
+ * finished, so a chain of tail calls runs in constant space: only the return
+ * types the functions it went through declare are kept, each once, to check
+ * the value at its end.
+ */
+fn apply(
+    closure: &Closure,
+    val_list: Vec<(ValueBind, Span)>,
+    span: Span,
+    caller: &ProgStates
+) -> Result<ValueBind, RuntimeErr> {
+    let mut flow = body_eval(closure, val_list, span, caller)?;
+    let mut current = (closure.def.clone(), span);
+    let mut pending: Vec<(Rc<FunDef>, Span, Span)> = Vec::new(); /* function, its call site, its tail call */
+    loop {
+        match flow {
+            Flow::TailCall(callee, val_list, tail_span) => {
+                let (def, call_site) = current;
+                if def.return_type.is_some() && !pending.iter().any(|(seen, _, _)| seen.return_type == def.return_type) {
+                    pending.push((def, call_site, tail_span));
+                }
+                flow = body_eval(&callee, val_list, tail_span, caller)?;
+                current = (callee.def.clone(), tail_span);
             }
-        }
-        let result = ast_eval(func_body, states.clone(), &mut func_list.clone());
-        let func_result: Result<ValueBind, RuntimeErr>;
-        match result {
-            Err(some_err) => match some_err {
-                RuntimeErr::ReturnValue(expr_value) => {
-                    if expr_value.get_type().eq_ignore_ascii_case(&func_type) {
-                        func_result = Ok(expr_value);
-                    } else {
-                        func_result = Err(RuntimeErr::TypeMismatch);
+            Flow::Return(expr_value, _) => {
+                for (def, call_site, tail_span) in pending.iter().rev() {
+                    let func_type = def.return_type.as_ref().unwrap();
+                    if !fits(func_type, &expr_value.get_value()) {
+                        let err = RuntimeErr::TypeMismatch(
+                            func_type.to_string(),
+                            expr_value.get_type().to_string(),
+                            *tail_span,
+                        );
+                        return Err(err.in_function(&def.name, *call_site));
                     }
                 }
-                _ => func_result = Err(some_err),
-            },
-            Ok(_) => func_result = Err(RuntimeErr::FunctionNoReturn),
+                return Ok(expr_value);
+            }
+            _ => unreachable!(),
         }
-        states.pop_front();
-        return func_result;
-    } else {
-        return Err(RuntimeErr::FuncallParaNum);
     }
 }
 
-fn expr_eval(expr: PistoletExpr, state: ProgStates, func_list: FuncDic) -> Result<ValueBind, RuntimeErr> {
-    match expr {
-        PistoletExpr::Val(value) => match value {
-            PistoletValue::Integer(n) => Ok(ValueBind::Vb("int".to_string(), VarValue::Int(n))),
-            PistoletValue::Float(n) => Ok(ValueBind::Vb("float".to_string(), VarValue::Float(n))),
-            PistoletValue::Boolean(n) => Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n))),
-            PistoletValue::Var(n) => var_eval(n, state),
-            PistoletValue::Funcall(func_name, expr_list) => func_eval(func_name, expr_list, state, func_list.clone()),
-        },
-        PistoletExpr::Add(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("int".to_string(), VarValue::Int(n + m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("float".to_string(), VarValue::Float(n + m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => Err(RuntimeErr::TypeMismatch),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
+/*
+ * Runs a function body in the environment it was defined in, with the arguments
+ * bound in a new frame, to a value or a tail call. The body continues on a new
+ * piece of native stack when the current one runs low, so only the call depth
+ * limits recursion.
+ */
+fn body_eval(
+    closure: &Closure,
+    val_list: Vec<(ValueBind, Span)>,
+    span: Span,
+    caller: &ProgStates
+) -> Result<Flow, RuntimeErr> {
+    let def = &closure.def;
+    let para_list = &def.para_list;
+    if val_list.len() != para_list.len() {
+        return Err(RuntimeErr::FuncallParaNum(
+            def.name.clone(),
+            para_list.len(),
+            val_list.len(),
+            span,
+        ));
+    }
+    let states = closure.env.call_from(caller, span)?;
+    let sub_state = ProgState(Rc::new(RefCell::new(ProgList {
+        var_list: Rc::default()
+    })));
+    states.push_front(sub_state);
+    let in_function = |err: RuntimeErr| err.in_function(&def.name, span);
+    for (index, ((para_name, para_type), (val, arg_span))) in para_list.iter().zip(val_list).enumerate() {
+        match para_type {
+            Some(para_type) if !fits(para_type, &val.get_value()) => {
+                return Err(in_function(RuntimeErr::ParaTypeMismatch(
+                    def.name.as_str().into(),
+                    para_name.clone(),
+                    para_type.to_string(),
+                    val.get_type().to_string(),
+                    arg_span,
+                )));
             }
+            _ => states.bind(index, para_name, val, false),
         }
-        PistoletExpr::Sub(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("int".to_string(), VarValue::Int(n - m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("float".to_string(), VarValue::Float(n - m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => Err(RuntimeErr::TypeMismatch),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
-            }
+    }
+    let result = stacker::maybe_grow(RED_ZONE, STACK_GROWTH, || {
+        ast_eval(&def.body, &states, &mut closure.funcs.clone())
+    });
+    match result {
+        Ok(Flow::Return(expr_value, return_span)) => match &def.return_type {
+            Some(func_type) if !fits(func_type, &expr_value.get_value()) => Err(in_function(RuntimeErr::TypeMismatch(
+                func_type.to_string(),
+                expr_value.get_type().to_string(),
+                return_span,
+            ))),
+            _ => Ok(Flow::Return(expr_value, return_span)),
+        },
+        Ok(tail_call @ Flow::TailCall(_, _, _)) => Ok(tail_call),
+        Ok(Flow::Normal) => Err(RuntimeErr::FunctionNoReturn(def.name.clone(), span)),
+        /* a loop jump can not leave the function it is written in */
+        Ok(Flow::Break(label, jump_span)) => Err(in_function(RuntimeErr::Break(label, jump_span))),
+        Ok(Flow::Continue(label, jump_span)) => Err(in_function(RuntimeErr::Continue(label, jump_span))),
+        Err(some_err) => Err(in_function(some_err)),
+    }
+}
+
+fn named_fun_eval(name: &str, span: Span, func_list: &FuncDic) -> Option<ValueBind> {
+    let closure = VarValue::Func(Rc::new(func_list.find_func(name, span).ok()?));
+    Some(ValueBind::Vb(closure.get_type(), closure))
+}
+
+fn lambda_eval(
+    para_list: &PistoletAST,
+    return_type: &Option<Type>,
+    body: &Rc<PistoletAST>,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> ValueBind {
+    let def = FunDef::new("<fun>".to_string(), para_list, return_type.clone(), body.clone());
+    let closure = VarValue::Func(Rc::new(Closure {
+        def: Rc::new(def),
+        env: state.capture(),
+        funcs: func_list.clone(),
+    }));
+    ValueBind::Vb(closure.get_type(), closure)
+}
+
+fn call_eval(
+    callee: &PistoletExpr,
+    expr_list: &[PistoletExpr],
+    span: Span,
+    states: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    match expr_eval(callee, states, func_list)?.get_value() {
+        VarValue::Func(closure) => closure_eval(&closure, expr_list, span, states, func_list),
+        other => Err(RuntimeErr::TypeMismatch(
+            "function".to_string(),
+            other.get_type().to_string(),
+            span,
+        )),
+    }
+}
+
+fn ctor_eval(
+    ctor: &str,
+    type_name: String,
+    arity: usize,
+    expr_list: &[PistoletExpr],
+    span: Span,
+    states: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    if expr_list.len() != arity {
+        return Err(RuntimeErr::FuncallParaNum(ctor.to_string(), arity, expr_list.len(), span));
+    }
+    let mut args = Vec::new();
+    for expr in expr_list {
+        args.push(expr_eval(expr, states, func_list)?.get_value());
+    }
+    let variant = VarValue::Variant(type_name, ctor.to_string(), Parts::new(args));
+    Ok(ValueBind::Vb(variant.get_type(), variant))
+}
+
+/* runs the builtins declared in `Checker::builtin` */
+fn builtin_eval(
+    name: &str,
+    expr_list: &[PistoletExpr],
+    span: Span,
+    states: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let arity = match name {
+        "len" => 1,
+        "push" => 2,
+        _ => return Err(RuntimeErr::FuncUsedBeforeDefine(name.to_string(), span)),
+    };
+    if expr_list.len() != arity {
+        return Err(RuntimeErr::FuncallParaNum(name.to_string(), arity, expr_list.len(), span));
+    }
+    let mut val_list: Vec<(VarValue, Span)> = Vec::new();
+    for expr in expr_list.iter() {
+        let expr_val = expr_eval(expr, states, func_list)?;
+        val_list.push((expr_val.get_value(), expr.span()));
+    }
+    let items = match &val_list[0] {
+        (VarValue::Array(items), _) => items.clone(),
+        (other, arg_span) => {
+            return Err(RuntimeErr::TypeMismatch(
+                "array".to_string(),
+                other.get_type().to_string(),
+                *arg_span,
+            ))
         }
-        PistoletExpr::Mul(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("int".to_string(), VarValue::Int(n * m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("float".to_string(), VarValue::Float(n * m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => Err(RuntimeErr::TypeMismatch),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
-            }
+    };
+    if name == "push" {
+        items.borrow_mut().push(val_list[1].0.clone());
+    }
+    let len = items.borrow().len() as i128;
+    Ok(ValueBind::Vb(Type::Int, VarValue::Int(len)))
+}
+
+/* the array and the checked position that `array[index]` refers to */
+fn index_eval(
+    array: &PistoletExpr,
+    index: &PistoletExpr,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<(ArrayRef, usize), RuntimeErr> {
+    let items = match expr_eval(array, state, func_list)?.get_value() {
+        VarValue::Array(items) => items,
+        other => {
+            return Err(RuntimeErr::TypeMismatch(
+                "array".to_string(),
+                other.get_type().to_string(),
+                array.span(),
+            ))
         }
-        PistoletExpr::Div(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            if m == 0 {
-                                Err(RuntimeErr::DivideByZero)
-                            } else {
-                                Ok(ValueBind::Vb("int".to_string(), VarValue::Int(n / m)))
-                            }
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            let r = n / m;
-                            if r.is_infinite() {
-                                Err(RuntimeErr::DivideByZero)
-                            } else {
-                                Ok(ValueBind::Vb("float".to_string(), VarValue::Float(r)))
-                            }
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => Err(RuntimeErr::TypeMismatch),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
-            }
+    };
+    let i = match expr_eval(index, state, func_list)?.get_value() {
+        VarValue::Int(i) => i,
+        other => {
+            return Err(RuntimeErr::TypeMismatch(
+                "int".to_string(),
+                other.get_type().to_string(),
+                index.span(),
+            ))
         }
-        PistoletExpr::And(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Bool(n) => match v2 {
-                        VarValue::Bool(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n && m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => Err(RuntimeErr::TypeMismatch),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
+    };
+    let len = items.borrow().len();
+    if i < 0 || i >= len as i128 {
+        return Err(RuntimeErr::IndexOutOfBounds(i, len, index.span()));
+    }
+    Ok((items, i as usize))
+}
+
+fn fields_eval(
+    fields: &[(String, PistoletExpr)],
+    state: &ProgStates,
+    func_list: &FuncDic,
+) -> Result<Vec<(String, VarValue)>, RuntimeErr> {
+    let mut values = Vec::new();
+    for (field, expr) in fields {
+        values.push((field.clone(), expr_eval(expr, state, func_list)?.get_value()));
+    }
+    Ok(values)
+}
+
+/* a name the resolver found no variable for is a constructor without arguments, or a named function passed around as a value */
+fn var_eval(
+    name: &str,
+    slot: Option<Slot>,
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    if let Some(slot) = slot {
+        return state
+            .get(slot)
+            .ok_or_else(|| RuntimeErr::VarUsedBeforeDefine(name.to_string(), span));
+    }
+    if let Some((type_name, 0)) = func_list.find_ctor(name) {
+        let variant = VarValue::Variant(type_name, name.to_string(), Parts::new(Vec::new()));
+        return Ok(ValueBind::Vb(variant.get_type(), variant));
+    }
+    named_fun_eval(name, span, func_list).ok_or_else(|| RuntimeErr::VarUsedBeforeDefine(name.to_string(), span))
+}
+
+pub(crate) fn expr_eval(expr: &PistoletExpr, state: &ProgStates, func_list: &FuncDic) -> Result<ValueBind, RuntimeErr> {
+    match expr {
+        PistoletExpr::Val(value) => match value {
+            PistoletValue::Integer(n, _) => Ok(ValueBind::Vb(Type::Int, VarValue::Int(*n))),
+            PistoletValue::Float(n, _) => Ok(ValueBind::Vb(Type::Float, VarValue::Float(*n))),
+            PistoletValue::Boolean(n, _) => Ok(ValueBind::Vb(Type::Bool, VarValue::Bool(*n))),
+            PistoletValue::Str(n, _) => Ok(ValueBind::Vb(Type::Str, VarValue::Str(n.clone()))),
+            PistoletValue::Array(expr_list, _) => array_eval(expr_list, state, func_list),
+            PistoletValue::Tuple(expr_list, _) => tuple_eval(expr_list, state, func_list),
+            PistoletValue::Record(name, fields, _) => record_eval(name, fields, state, func_list),
+            PistoletValue::Var(n, slot, span) => var_eval(n, *slot, *span, state, func_list),
+            PistoletValue::Funcall(func_name, expr_list, span) => {
+                func_eval(func_name, expr_list, *span, state, func_list)
             }
+        },
+        PistoletExpr::Index(e, index, _) => element_eval(e, index, state, func_list),
+        PistoletExpr::Field(e, field, span) => field_eval(e, field, *span, state, func_list),
+        PistoletExpr::Update(e, updates, span) => update_eval(e, updates, *span, state, func_list),
+        PistoletExpr::Lambda(para_list, return_type, body, _) => {
+            Ok(lambda_eval(para_list, return_type, body, state, func_list))
         }
-        PistoletExpr::Orb(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Bool(n) => match v2 {
-                        VarValue::Bool(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n || m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => Err(RuntimeErr::TypeMismatch),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
+        PistoletExpr::Call(e, expr_list, span) => call_eval(e, expr_list, *span, state, func_list),
+        PistoletExpr::Match(e, arms, span) => match_eval(e, arms, *span, state, func_list),
+        PistoletExpr::Neg(e, span) => unary_eval("-", e, *span, state, func_list),
+        PistoletExpr::Not(e, span) => unary_eval("!", e, *span, state, func_list),
+        PistoletExpr::Add(e1, e2, span) => binary_eval("+", e1, e2, *span, state, func_list),
+        PistoletExpr::Sub(e1, e2, span) => binary_eval("-", e1, e2, *span, state, func_list),
+        PistoletExpr::Mul(e1, e2, span) => binary_eval("*", e1, e2, *span, state, func_list),
+        PistoletExpr::Div(e1, e2, span) => binary_eval("/", e1, e2, *span, state, func_list),
+        PistoletExpr::And(e1, e2, span) => binary_eval("&&", e1, e2, *span, state, func_list),
+        PistoletExpr::Orb(e1, e2, span) => binary_eval("||", e1, e2, *span, state, func_list),
+        PistoletExpr::Nand(e1, e2, span) => binary_eval("~&&", e1, e2, *span, state, func_list),
+        PistoletExpr::Eq(e1, e2, span) => binary_eval("=?", e1, e2, *span, state, func_list),
+        PistoletExpr::Leq(e1, e2, span) => binary_eval("<=", e1, e2, *span, state, func_list),
+        PistoletExpr::Req(e1, e2, span) => binary_eval(">=", e1, e2, *span, state, func_list),
+        PistoletExpr::Left(e1, e2, span) => binary_eval("<", e1, e2, *span, state, func_list),
+        PistoletExpr::Right(e1, e2, span) => binary_eval(">", e1, e2, *span, state, func_list),
+    }
+}
+
+fn array_eval(expr_list: &[PistoletExpr], state: &ProgStates, func_list: &FuncDic) -> Result<ValueBind, RuntimeErr> {
+    let mut items: Vec<VarValue> = Vec::new();
+    for expr in expr_list {
+        let item = expr_eval(expr, state, func_list)?.get_value();
+        if let Some(first) = items.first() {
+            if !type_dec(first, &item) {
+                return Err(RuntimeErr::TypeMismatch(
+                    first.get_type().to_string(),
+                    item.get_type().to_string(),
+                    expr.span(),
+                ));
             }
         }
-        PistoletExpr::Nand(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Bool(n) => match v2 {
-                        VarValue::Bool(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(!(n && m))))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => Err(RuntimeErr::TypeMismatch),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
-            }
+        items.push(item);
+    }
+    let array = VarValue::Array(Rc::new(RefCell::new(items)));
+    Ok(ValueBind::Vb(array.get_type(), array))
+}
+
+fn tuple_eval(expr_list: &[PistoletExpr], state: &ProgStates, func_list: &FuncDic) -> Result<ValueBind, RuntimeErr> {
+    let mut items: Vec<VarValue> = Vec::new();
+    for expr in expr_list {
+        items.push(expr_eval(expr, state, func_list)?.get_value());
+    }
+    let tuple = VarValue::Tuple(Parts::new(items));
+    Ok(ValueBind::Vb(tuple.get_type(), tuple))
+}
+
+fn record_eval(
+    name: &str,
+    fields: &[(String, PistoletExpr)],
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let record = VarValue::Record(name.to_string(), Fields::new(fields_eval(fields, state, func_list)?));
+    Ok(ValueBind::Vb(record.get_type(), record))
+}
+
+fn element_eval(
+    array: &PistoletExpr,
+    index: &PistoletExpr,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let (items, i) = index_eval(array, index, state, func_list)?;
+    let item = items.borrow()[i].clone();
+    Ok(ValueBind::Vb(item.get_type(), item))
+}
+
+fn field_eval(
+    e: &PistoletExpr,
+    field: &str,
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let b = expr_eval(e, state, func_list)?;
+    let value = match b.get_value() {
+        VarValue::Record(_, fields) => fields
+            .iter()
+            .find(|(name, _)| name == field)
+            .map(|(_, value)| value.clone()),
+        _ => None,
+    };
+    match value {
+        Some(value) => Ok(ValueBind::Vb(value.get_type(), value)),
+        None => Err(RuntimeErr::NoField(b.get_type().to_string(), field.to_string(), span)),
+    }
+}
+
+fn update_eval(
+    e: &PistoletExpr,
+    updates: &[(String, PistoletExpr)],
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let b = expr_eval(e, state, func_list)?;
+    let (name, mut fields) = match b.get_value() {
+        VarValue::Record(name, fields) => (name, fields.to_vec()),
+        other => {
+            return Err(RuntimeErr::TypeMismatch(
+                "record".to_string(),
+                other.get_type().to_string(),
+                span,
+            ))
         }
-        PistoletExpr::Eq(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n == m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n == m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => unreachable!(),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
-            }
+    };
+    for (field, value) in fields_eval(updates, state, func_list)? {
+        match fields.iter_mut().find(|(name, _)| *name == field) {
+            Some((_, old)) => *old = value,
+            None => return Err(RuntimeErr::NoField(name, field, span)),
         }
-        PistoletExpr::Leq(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n <= m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n <= m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => unreachable!(),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
-            }
+    }
+    let record = VarValue::Record(name, Fields::new(fields));
+    Ok(ValueBind::Vb(record.get_type(), record))
+}
+
+fn match_eval(
+    e: &PistoletExpr,
+    arms: &[(Pattern, Option<PistoletExpr>, PistoletExpr)],
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    arm_eval(e, arms, span, state, func_list, |arm_value| expr_eval(arm_value, state, func_list))
+}
+
+/* evaluates the value of the first arm taken with `eval`, in a frame holding what its pattern bound */
+fn arm_eval<T>(
+    e: &PistoletExpr,
+    arms: &[(Pattern, Option<PistoletExpr>, PistoletExpr)],
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic,
+    eval: impl Fn(&PistoletExpr) -> Result<T, RuntimeErr>,
+) -> Result<T, RuntimeErr> {
+    let value = expr_eval(e, state, func_list)?.get_value();
+    for (pattern, guard, arm_value) in arms {
+        state.push_front(ProgState(Rc::new(RefCell::new(ProgList {
+            var_list: Rc::default()
+        }))));
+        let result = match arm_matches(pattern, guard, &value, state, func_list) {
+            Ok(true) => Some(eval(arm_value)),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        };
+        state.pop_front();
+        if let Some(result) = result {
+            return result;
         }
-        PistoletExpr::Req(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n >= m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n >= m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => unreachable!(),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
+    }
+    Err(RuntimeErr::MatchFailure(value.to_string(), span))
+}
+
+fn unary_eval(
+    op: &str,
+    e: &PistoletExpr,
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let b = expr_eval(e, state, func_list)?;
+    match (op, b.get_value()) {
+        ("-", VarValue::Int(n)) => Ok(ValueBind::Vb(Type::Int, VarValue::Int(n.checked_neg().ok_or(RuntimeErr::Overflow(span))?))),
+        ("-", VarValue::Float(n)) => Ok(ValueBind::Vb(Type::Float, VarValue::Float(-n))),
+        ("!", VarValue::Bool(n)) => Ok(ValueBind::Vb(Type::Bool, VarValue::Bool(!n))),
+        _ => Err(RuntimeErr::TypeMismatch(
+            if op == "-" { "int or float" } else { "bool" }.to_string(),
+            b.get_type().to_string(),
+            span,
+        )),
+    }
+}
+
+fn binary_eval(
+    op: &str,
+    e1: &PistoletExpr,
+    e2: &PistoletExpr,
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<ValueBind, RuntimeErr> {
+    let b1 = expr_eval(e1, state, func_list)?;
+    let b2 = expr_eval(e2, state, func_list)?;
+    operate(op, b1, b2, span)
+}
+
+/* kept apart from `binary_eval` so its locals are not on the stack of every recursive call */
+fn operate(op: &str, b1: ValueBind, b2: ValueBind, span: Span) -> Result<ValueBind, RuntimeErr> {
+    let v1 = b1.get_value();
+    let v2 = b2.get_value();
+    if !type_dec(&v1, &v2) {
+        return Err(RuntimeErr::TypeMismatch(b1.get_type().to_string(), b2.get_type().to_string(), span));
+    }
+    let result = match (op, v1, v2) {
+        ("+", VarValue::Int(n), VarValue::Int(m)) => VarValue::Int(n.checked_add(m).ok_or(RuntimeErr::Overflow(span))?),
+        ("+", VarValue::Float(n), VarValue::Float(m)) => VarValue::Float(n + m),
+        ("+", VarValue::Str(n), VarValue::Str(m)) => VarValue::Str(n + &m),
+        ("-", VarValue::Int(n), VarValue::Int(m)) => VarValue::Int(n.checked_sub(m).ok_or(RuntimeErr::Overflow(span))?),
+        ("-", VarValue::Float(n), VarValue::Float(m)) => VarValue::Float(n - m),
+        ("*", VarValue::Int(n), VarValue::Int(m)) => VarValue::Int(n.checked_mul(m).ok_or(RuntimeErr::Overflow(span))?),
+        ("*", VarValue::Float(n), VarValue::Float(m)) => VarValue::Float(n * m),
+        ("/", VarValue::Int(_), VarValue::Int(0)) => return Err(RuntimeErr::DivideByZero(span)),
+        ("/", VarValue::Int(n), VarValue::Int(m)) => VarValue::Int(n.checked_div(m).ok_or(RuntimeErr::Overflow(span))?),
+        ("/", VarValue::Float(n), VarValue::Float(m)) => {
+            let r = n / m;
+            if r.is_infinite() {
+                return Err(RuntimeErr::DivideByZero(span));
             }
+            VarValue::Float(r)
         }
-        PistoletExpr::Left(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n < m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n < m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => unreachable!(),
-                }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
-            }
+        ("&&", VarValue::Bool(n), VarValue::Bool(m)) => VarValue::Bool(n && m),
+        ("||", VarValue::Bool(n), VarValue::Bool(m)) => VarValue::Bool(n || m),
+        ("~&&", VarValue::Bool(n), VarValue::Bool(m)) => VarValue::Bool(!(n && m)),
+        ("=?", VarValue::Int(n), VarValue::Int(m)) => VarValue::Bool(n == m),
+        ("=?", VarValue::Float(n), VarValue::Float(m)) => VarValue::Bool(n == m),
+        ("=?", VarValue::Str(n), VarValue::Str(m)) => VarValue::Bool(n == m),
+        ("<=", VarValue::Int(n), VarValue::Int(m)) => VarValue::Bool(n <= m),
+        ("<=", VarValue::Float(n), VarValue::Float(m)) => VarValue::Bool(n <= m),
+        ("<=", VarValue::Str(n), VarValue::Str(m)) => VarValue::Bool(n <= m),
+        (">=", VarValue::Int(n), VarValue::Int(m)) => VarValue::Bool(n >= m),
+        (">=", VarValue::Float(n), VarValue::Float(m)) => VarValue::Bool(n >= m),
+        (">=", VarValue::Str(n), VarValue::Str(m)) => VarValue::Bool(n >= m),
+        ("<", VarValue::Int(n), VarValue::Int(m)) => VarValue::Bool(n < m),
+        ("<", VarValue::Float(n), VarValue::Float(m)) => VarValue::Bool(n < m),
+        ("<", VarValue::Str(n), VarValue::Str(m)) => VarValue::Bool(n < m),
+        (">", VarValue::Int(n), VarValue::Int(m)) => VarValue::Bool(n > m),
+        (">", VarValue::Float(n), VarValue::Float(m)) => VarValue::Bool(n > m),
+        (">", VarValue::Str(n), VarValue::Str(m)) => VarValue::Bool(n > m),
+        (op, _, _) => {
+            let expected = match op {
+                "-" | "*" | "/" => "int or float",
+                "&&" | "||" | "~&&" => "bool",
+                _ => "int, float or string",
+            };
+            return Err(RuntimeErr::TypeMismatch(expected.to_string(), b1.get_type().to_string(), span));
         }
-        PistoletExpr::Right(e1, e2) => {
-            let v1 = expr_eval(*e1, state.clone(), func_list.clone())?;
-            let v2 = expr_eval(*e2, state.clone(), func_list.clone())?;
-            let v1 = v1.get_value();
-            let v2 = v2.get_value();
-
-            if type_dec(v1, v2) {
-                match v1 {
-                    VarValue::Int(n) => match v2 {
-                        VarValue::Int(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n > m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    VarValue::Float(n) => match v2 {
-                        VarValue::Float(m) => {
-                            Ok(ValueBind::Vb("bool".to_string(), VarValue::Bool(n > m)))
-                        }
-                        _ => unreachable!(),
-                    },
-                    _ => unreachable!(),
+    };
+    Ok(ValueBind::Vb(result.get_type(), result))
+}
+
+/* what a loop does after its body finished with `flow`: None goes on, Some leaves with that flow */
+fn loop_exit(flow: Flow, label: &Option<String>) -> Option<Flow> {
+    match flow {
+        Flow::Normal => None,
+        Flow::Break(target, _) if target.is_none() || target == *label => Some(Flow::Normal),
+        Flow::Continue(target, _) if target.is_none() || target == *label => None,
+        other => Some(other),
+    }
+}
+
+/* one run of a loop body in a frame of its own, holding the loop variable if there is one */
+fn iteration_eval(
+    seq: &PistoletAST,
+    label: &Option<String>,
+    var: Option<(&Rc<str>, VarValue)>, /* the first binding of the frame */
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Option<Flow>, RuntimeErr> {
+    state.push_front(ProgState(Rc::new(RefCell::new(ProgList {
+        var_list: Rc::default()
+    }))));
+    if let Some((var_name, value)) = var {
+        state.bind(0, var_name, ValueBind::Vb(value.get_type(), value), false);
+    }
+    let result = seq_eval(seq, state, func_list);
+    state.pop_front();
+    Ok(loop_exit(result?, label))
+}
+
+/*
+ * Functions and constructors are visible to the whole block defining them, as the
+ * checker and the resolver see them, so they are registered before it runs. Until
+ * its definition runs, a function sees the bindings of the block as they are when
+ * it is called; the definition then keeps the ones it sees, like a `fun` value.
+ */
+fn hoist_eval(term_list: &[PistoletAST], state: &ProgStates, func_list: &mut FuncDic) {
+    for term in term_list {
+        match term {
+            PistoletAST::Fun(func_name, para_list, return_type, fun_body, _) => {
+                func_list.func_insert(func_name.clone(), para_list, return_type.clone(), fun_body.clone(), state.branch());
+            }
+            PistoletAST::TypeDef(type_name, TypeDef::Variant(ctors), _) => {
+                for (ctor, args) in ctors {
+                    func_list.ctor_insert(ctor.clone(), type_name.clone(), args.len());
                 }
-            } else {
-                Err(RuntimeErr::TypeMismatch)
             }
+            _ => {}
         }
     }
 }
 
-fn seq_eval(ast: PistoletAST, state: ProgStates, mut func_list: FuncDic) -> Option<RuntimeErr> {
-    let mut error: RuntimeErr = RuntimeErr::Unknown;
-    let mut error_state = false;
+/* runs the statements of a block in order until one of them leaves it */
+fn seq_eval(ast: &PistoletAST, state: &ProgStates, func_list: &FuncDic) -> Result<Flow, RuntimeErr> {
+    let mut func_list = func_list.enter();
     match ast {
-        PistoletAST::Seq(term_list) => {
+        PistoletAST::Seq(term_list, _) => {
+            hoist_eval(term_list, state, &mut func_list);
             for term in term_list {
-                match ast_eval(term, state.clone(), &mut func_list) {
-                    Ok(_) => continue,
-                    Err(err) => {
-                        error = err;
-                        error_state = true;
-                        break;
-                    }
+                match ast_eval(term, state, &mut func_list)? {
+                    Flow::Normal => continue,
+                    flow => return Ok(flow),
                 }
             }
+            Ok(Flow::Normal)
         }
         _ => unreachable!(),
     }
-    if error_state {
-        Some(error)
-    } else {
-        None
-    }
 }
 
-fn ast_eval(ast: PistoletAST, state: ProgStates, func_list: &mut FuncDic) -> Result<(ProgStates, FuncDic), RuntimeErr> {
-    match ast {
-        PistoletAST::Seq(term_list) => match seq_eval(PistoletAST::Seq(term_list), state.clone(), func_list.clone()) {
-            Some(err) => Err(err),
-            None => Ok((state.clone(), func_list.clone())),
+/* binds the names of `pattern` in the innermost state if it matches, a failed match may leave some bound */
+fn match_pattern(pattern: &Pattern, value: &VarValue, state: &ProgStates) -> bool {
+    match (pattern, value) {
+        (Pattern::Var(var_name, index, _), value) => {
+            state.bind(*index, var_name, ValueBind::Vb(value.get_type(), value.clone()), false);
+            true
+        }
+        (Pattern::Wildcard(_), _) => true,
+        (Pattern::Tuple(patterns, _), VarValue::Tuple(items)) => {
+            patterns.len() == items.len()
+                && patterns.iter().zip(items.iter()).all(|(pattern, item)| match_pattern(pattern, item, state))
+        }
+        (Pattern::Literal(literal), value) => match (literal, value) {
+            (PistoletValue::Integer(n, _), VarValue::Int(m)) => n == m,
+            (PistoletValue::Float(n, _), VarValue::Float(m)) => n == m,
+            (PistoletValue::Boolean(n, _), VarValue::Bool(m)) => n == m,
+            (PistoletValue::Str(n, _), VarValue::Str(m)) => n == m,
+            _ => false,
         },
-        PistoletAST::Let(var_name, var_type, var_expr) => {
-            let var_value = expr_eval(var_expr, state.clone(), func_list.clone())?;
-            if var_value.get_type().eq_ignore_ascii_case(&var_type) {
-                state.insert(var_name, var_value);
-                Ok((state.clone(), func_list.clone()))
-            } else {
-                Err(RuntimeErr::TypeMismatch)
+        (Pattern::Ctor(name, arg, _), VarValue::Variant(_, ctor, args)) if name == ctor => {
+            match Pattern::ctor_args(arg.as_deref(), args.len()) {
+                Some(patterns) => patterns
+                    .iter()
+                    .zip(args.iter())
+                    .all(|(pattern, arg)| match_pattern(pattern, arg, state)),
+                None => false,
             }
         }
-        PistoletAST::If(expr, branch_true, branch_false) => {
-            let expr_value = expr_eval(expr, state.clone(), func_list.clone())?;
-            let sub_state = ProgState(Rc::new(RefCell::new(ProgList {
-                var_list: HashMap::new()
-            })));
-            state.push_front(sub_state);
-            match expr_value.get_value() {
-                VarValue::Bool(true) => match seq_eval(*branch_true, state.clone(), func_list.clone()) {
-                    Some(err) => {
-                        state.pop_front();
-                        Err(err)
-                    }
-                    None => {
-                        state.pop_front();
-                        Ok((state.clone(), func_list.clone()))
-                    }
-                },
-                VarValue::Bool(false) => match seq_eval(*branch_false, state.clone(), func_list.clone()) {
-                    Some(err) => {
-                        state.pop_front();
-                        Err(err)
-                    }
-                    None => {
-                        state.pop_front();
-                        Ok((state.clone(), func_list.clone()))
-                    }
-                },
-                _ => unreachable!(),
+        _ => false,
+    }
+}
+
+/* whether an arm is taken: its pattern matches and its guard, if any, holds */
+fn arm_matches(
+    pattern: &Pattern,
+    guard: &Option<PistoletExpr>,
+    value: &VarValue,
+    state: &ProgStates,
+    func_list: &FuncDic,
+) -> Result<bool, RuntimeErr> {
+    if !match_pattern(pattern, value, state) {
+        return Ok(false);
+    }
+    match guard {
+        Some(guard) => cond_eval(guard, state, func_list),
+        None => Ok(true),
+    }
+}
+
+fn bind_pattern(
+    pattern: &Pattern,
+    value: VarValue,
+    mutable: bool,
+    state: &ProgStates,
+    span: Span
+) -> Result<(), RuntimeErr> {
+    match (pattern, value) {
+        (Pattern::Var(var_name, index, _), value) => {
+            state.bind(*index, var_name, ValueBind::Vb(value.get_type(), value), mutable);
+            Ok(())
+        }
+        (Pattern::Tuple(patterns, _), VarValue::Tuple(items)) if patterns.len() == items.len() => {
+            for (pattern, item) in patterns.iter().zip(items.iter()) {
+                bind_pattern(pattern, item.clone(), mutable, state, span)?;
             }
+            Ok(())
         }
-        PistoletAST::While(seq, expr) => {
-            let info: Result<(ProgStates, FuncDic), RuntimeErr>;
-            let sub_state = ProgState(Rc::new(RefCell::new(ProgList {
-                var_list: HashMap::new()
-            })));
-            state.push_front(sub_state);
-            loop {
-                match seq_eval(*seq.clone(), state.clone(), func_list.clone()) {
-                    Some(err) => {
-                        info = Err(err);
-                        break;
-                    } //Here can process break. in future...
-                    None => {
-                        let expr_value = expr_eval(expr.clone(), state.clone(), func_list.clone())?.get_value();
-                        match expr_value {
-                            VarValue::Bool(true) => {
-                                info = Ok((state.clone(), func_list.clone()));
-                                break;
-                            }
-                            VarValue::Bool(false) => {
-                                continue;
-                            }
-                            _ => unreachable!(),
-                        }
-                    }
+        (Pattern::Tuple(patterns, _), value) => Err(RuntimeErr::TypeMismatch(
+            format!("tuple of {}", patterns.len()),
+            value.get_type().to_string(),
+            span,
+        )),
+        (pattern, value) if match_pattern(pattern, &value, state) => Ok(()),
+        (_, value) => Err(RuntimeErr::MatchFailure(value.to_string(), span)),
+    }
+}
+
+fn cond_eval(expr: &PistoletExpr, state: &ProgStates, func_list: &FuncDic) -> Result<bool, RuntimeErr> {
+    let expr_value = expr_eval(expr, state, func_list)?;
+    match expr_value.get_value() {
+        VarValue::Bool(b) => Ok(b),
+        _ => Err(RuntimeErr::TypeMismatch(
+            "bool".to_string(),
+            expr_value.get_type().to_string(),
+            expr.span(),
+        )),
+    }
+}
+
+fn let_eval(
+    pattern: &Pattern,
+    mutable: bool,
+    var_type: &Option<Type>,
+    var_expr: &PistoletExpr,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Flow, RuntimeErr> {
+    let var_value = expr_eval(var_expr, state, func_list)?;
+    match var_type {
+        Some(var_type) if !fits(var_type, &var_value.get_value()) => Err(RuntimeErr::TypeMismatch(
+            var_type.to_string(),
+            var_value.get_type().to_string(),
+            var_expr.span(),
+        )),
+        _ => {
+            bind_pattern(pattern, var_value.get_value(), mutable, state, var_expr.span())?;
+            Ok(Flow::Normal)
+        }
+    }
+}
+
+fn if_eval(
+    expr: &PistoletExpr,
+    branch_true: &PistoletAST,
+    branch_false: &PistoletAST,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Flow, RuntimeErr> {
+    let expr_value = cond_eval(expr, state, func_list)?;
+    let sub_state = ProgState(Rc::new(RefCell::new(ProgList {
+        var_list: Rc::default()
+    })));
+    state.push_front(sub_state);
+    let branch = if expr_value { branch_true } else { branch_false };
+    let result = seq_eval(branch, state, func_list);
+    state.pop_front();
+    result
+}
+
+fn match_stmt_eval(
+    expr: &PistoletExpr,
+    arms: &[(Pattern, Option<PistoletExpr>, PistoletAST)],
+    span: Span,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Flow, RuntimeErr> {
+    let value = expr_eval(expr, state, func_list)?.get_value();
+    for (pattern, guard, body) in arms {
+        state.push_front(ProgState(Rc::new(RefCell::new(ProgList {
+            var_list: Rc::default()
+        }))));
+        let result = match arm_matches(pattern, guard, &value, state, func_list) {
+            Ok(true) => Some(seq_eval(body, state, func_list)),
+            Ok(false) => None,
+            Err(err) => Some(Err(err)),
+        };
+        state.pop_front();
+        if let Some(result) = result {
+            return result;
+        }
+    }
+    Err(RuntimeErr::MatchFailure(value.to_string(), span))
+}
+
+/* `loop ... ~> expr`, in one frame for every run of the body */
+fn do_until_eval(
+    seq: &PistoletAST,
+    expr: &PistoletExpr,
+    label: &Option<String>,
+    carried: &[(usize, Slot)],
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Flow, RuntimeErr> {
+    let sub_state = ProgState(Rc::new(RefCell::new(ProgList {
+        var_list: Rc::default()
+    })));
+    for (index, outside) in carried {
+        if let Some(cell) = state.cell(*outside) {
+            sub_state.set(*index, cell);
+        }
+    }
+    state.push_front(sub_state);
+    let info = loop {
+        match seq_eval(seq, state, func_list) {
+            Err(err) => break Err(err),
+            Ok(flow) => {
+                if let Some(flow) = loop_exit(flow, label) {
+                    break Ok(flow);
                 }
             }
-            state.pop_front();
-            info
         }
-        PistoletAST::Fun(func_name, para_list, return_type, fun_body) => {
-            func_list.func_insert(func_name, *para_list, return_type, *fun_body);
-            Ok((state.clone(), func_list.clone()))
+        match cond_eval(expr, state, func_list) {
+            Ok(true) => break Ok(Flow::Normal),
+            Ok(false) => continue,
+            Err(err) => break Err(err),
+        }
+    };
+    state.pop_front();
+    info
+}
+
+fn while_eval(
+    expr: &PistoletExpr,
+    seq: &PistoletAST,
+    label: &Option<String>,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Flow, RuntimeErr> {
+    while cond_eval(expr, state, func_list)? {
+        if let Some(flow) = iteration_eval(seq, label, None, state, func_list)? {
+            return Ok(flow);
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+fn for_eval(ast: &PistoletAST, state: &ProgStates, func_list: &FuncDic) -> Result<Flow, RuntimeErr> {
+    let (var_name, start, end, seq, label, span) = match ast {
+        PistoletAST::For(var_name, start, end, seq, label, span) => (var_name, start, end, seq, label, *span),
+        _ => unreachable!(),
+    };
+    let start = expr_eval(start, state, func_list)?.get_value();
+    let items = match (start, end) {
+        (VarValue::Int(from), Some(end)) => match expr_eval(end, state, func_list)?.get_value() {
+            VarValue::Int(to) => (from..to).map(VarValue::Int).collect(),
+            other => return Err(RuntimeErr::TypeMismatch("int".to_string(), other.get_type().to_string(), span)),
+        },
+        /* the elements as they are when the loop starts */
+        (VarValue::Array(items), None) => items.borrow().clone(),
+        (other, Some(_)) => return Err(RuntimeErr::TypeMismatch("int".to_string(), other.get_type().to_string(), span)),
+        (other, None) => return Err(RuntimeErr::TypeMismatch("array".to_string(), other.get_type().to_string(), span)),
+    };
+    for item in items {
+        if let Some(flow) = iteration_eval(seq, label, Some((var_name, item)), state, func_list)? {
+            return Ok(flow);
+        }
+    }
+    Ok(Flow::Normal)
+}
+
+/*
+ * A function called by `return`, also as the value of an arm of the `match` it
+ * returns, is left to `apply`, which calls it after the frames of this body are gone.
+ */
+fn return_eval(expr: &PistoletExpr, span: Span, state: &ProgStates, func_list: &FuncDic) -> Result<Flow, RuntimeErr> {
+    let tail_call = match expr {
+        PistoletExpr::Match(e, arms, match_span) => {
+            return arm_eval(e, arms, *match_span, state, func_list, |arm_value| {
+                return_eval(arm_value, span, state, func_list)
+            })
+        }
+        PistoletExpr::Val(PistoletValue::Funcall(func_name, expr_list, call_span)) => func_list
+            .find_func(func_name, *call_span)
+            .ok()
+            .map(|closure| (Rc::new(closure), expr_list, *call_span)),
+        PistoletExpr::Call(callee, expr_list, call_span) => match expr_eval(callee, state, func_list)?.get_value() {
+            VarValue::Func(closure) => Some((closure, expr_list, *call_span)),
+            other => {
+                return Err(RuntimeErr::TypeMismatch(
+                    "function".to_string(),
+                    other.get_type().to_string(),
+                    *call_span,
+                ))
+            }
+        },
+        _ => None,
+    };
+    match tail_call {
+        Some((closure, expr_list, call_span)) => {
+            Ok(Flow::TailCall(closure, args_eval(expr_list, state, func_list)?, call_span))
+        }
+        None => Ok(Flow::Return(expr_eval(expr, state, func_list)?, span)),
+    }
+}
+
+fn print_eval(expr: &PistoletExpr, state: &ProgStates, func_list: &FuncDic) -> Result<Flow, RuntimeErr> {
+    let expr_value = expr_eval(expr, state, func_list)?;
+    match expr_value.get_value() {
+        /* strings print bare so they can be used as labels */
+        VarValue::Str(s) => println!("{}", s),
+        other => println!("{} : {}", other, expr_value.get_type()),
+    }
+    Ok(Flow::Normal)
+}
+
+fn assign_eval(
+    target: &PistoletExpr,
+    value: &PistoletExpr,
+    state: &ProgStates,
+    func_list: &FuncDic
+) -> Result<Flow, RuntimeErr> {
+    /* the value first, it may change the array's length */
+    let value = expr_eval(value, state, func_list)?;
+    match target {
+        PistoletExpr::Index(e, index, _) => {
+            let (items, i) = index_eval(e, index, state, func_list)?;
+            items.borrow_mut()[i] = value.get_value();
+            Ok(Flow::Normal)
+        }
+        PistoletExpr::Val(PistoletValue::Var(var_name, Some(slot), span)) => {
+            state.assign(var_name, *slot, value, *span)?;
+            Ok(Flow::Normal)
+        }
+        other => Err(RuntimeErr::Unknown(other.span())),
+    }
+}
+
+/* a dispatch only, every statement with locals of its own is evaluated apart to keep the recursion's frames small */
+pub(crate) fn ast_eval(ast: &PistoletAST, state: &ProgStates, func_list: &mut FuncDic) -> Result<Flow, RuntimeErr> {
+    match ast {
+        PistoletAST::Seq(_, _) => seq_eval(ast, state, func_list),
+        PistoletAST::Let(pattern, mutable, var_type, var_expr, _) => {
+            let_eval(pattern, *mutable, var_type, var_expr, state, func_list)
+        }
+        PistoletAST::If(expr, branch_true, branch_false, _) => {
+            if_eval(expr, branch_true, branch_false, state, func_list)
+        }
+        PistoletAST::Match(expr, arms, span) => match_stmt_eval(expr, arms, *span, state, func_list),
+        PistoletAST::While(seq, expr, label, carried, _) => {
+            do_until_eval(seq, expr, label, carried, state, func_list)
+        }
+        PistoletAST::WhileDo(expr, seq, label, _) => while_eval(expr, seq, label, state, func_list),
+        PistoletAST::For(_, _, _, _, _, _) => for_eval(ast, state, func_list),
+        PistoletAST::Fun(func_name, para_list, return_type, fun_body, _) => {
+            func_list.func_insert(func_name.clone(), para_list, return_type.clone(), fun_body.clone(), state.capture());
+            Ok(Flow::Normal)
         }
-        PistoletAST::Return(expr) => {
-            let expr_value = expr_eval(expr, state, func_list.clone())?;
-            Err(RuntimeErr::ReturnValue(expr_value))
+        PistoletAST::Return(expr, span) => return_eval(expr, *span, state, func_list),
+        PistoletAST::Break(label, span) => Ok(Flow::Break(label.clone(), *span)),
+        PistoletAST::Continue(label, span) => Ok(Flow::Continue(label.clone(), *span)),
+        PistoletAST::PrintLine(expr, _) => print_eval(expr, state, func_list),
+        PistoletAST::Assign(target, value, _) => assign_eval(target, value, state, func_list),
+        PistoletAST::Call(expr, _) => {
+            expr_eval(expr, state, func_list)?;
+            Ok(Flow::Normal)
+        }
+        /* constructors were registered with the block, record declarations only matter to the checker */
+        PistoletAST::TypeDef(_, _, _) | PistoletAST::EOI => Ok(Flow::Normal),
+        other => Err(RuntimeErr::Unknown(other.span())),
+    }
+}
+
+/* One session of evaluation: the global state and functions survive between calls to `run` */
+#[derive(Debug, Clone)]
+pub struct Interpreter {
+    state: ProgStates,
+    func_list: FuncDic,
+    checker: Checker,
+    resolver: Resolver,
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            state: ProgStates::new(),
+            func_list: FuncDic::new(),
+            checker: Checker::new(),
+            resolver: Resolver::new(),
         }
-        PistoletAST::PrintLine(expr) => {
-            let expr_value = expr_eval(expr, state.clone(), func_list.clone())?;
-            println!("{} : {}", expr_value.get_value(), expr_value.get_type());
-            Ok((state.clone(), func_list.clone()))
+    }
+
+    /* type checks a program against everything this session has defined so far */
+    pub fn check(&mut self, ast: &PistoletAST) -> Result<(), Vec<TypeErr>> {
+        self.checker.check(ast)
+    }
+
+    pub fn type_of(&mut self, expr: &PistoletExpr) -> Result<String, Vec<TypeErr>> {
+        self.checker.check_expr(expr)
+    }
+
+    /* warnings of the last `check` or `type_of` */
+    pub fn warnings(&self) -> &[TypeWarning] {
+        self.checker.warnings()
+    }
+
+    /*
+     * Resolves the variables of the top-level statements, then runs them in order.
+     * A top-level `return` stops the program with its value.
+     */
+    pub fn run(&mut self, ast: PistoletAST) -> Result<Option<ValueBind>, RunErr> {
+        let mut term_list = match ast {
+            PistoletAST::Seq(term_list, _) => term_list,
+            other => vec![other],
+        };
+        self.resolver.resolve(&mut term_list).map_err(RunErr::Unresolved)?;
+        hoist_eval(&term_list, &self.state, &mut self.func_list);
+        for term in &term_list {
+            match ast_eval(term, &self.state, &mut self.func_list)? {
+                Flow::Normal => continue,
+                Flow::Return(expr_value, _) => return Ok(Some(expr_value)),
+                Flow::TailCall(closure, val_list, span) => {
+                    return Ok(Some(apply(&closure, val_list, span, &self.state)?))
+                }
+                Flow::Break(label, span) => return Err(RuntimeErr::Break(label, span).into()),
+                Flow::Continue(label, span) => return Err(RuntimeErr::Continue(label, span).into()),
+            }
         }
-        PistoletAST::EOI => Ok((state.clone(), func_list.clone())),
-        _ => Err(RuntimeErr::Unknown),
+        Ok(None)
+    }
+
+    pub fn eval_expr(&mut self, mut expr: PistoletExpr) -> Result<ValueBind, RunErr> {
+        self.resolver.resolve_expr(&mut expr).map_err(RunErr::Unresolved)?;
+        Ok(expr_eval(&expr, &self.state, &self.func_list)?)
+    }
+
+    pub fn state(&self) -> &ProgStates {
+        &self.state
+    }
+
+    /* how many function calls may be active at once, DEFAULT_MAX_DEPTH unless set */
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.state.set_max_depth(max_depth);
+    }
+
+    /* inferred signatures of the functions defined so far */
+    pub fn functions(&self) -> Vec<String> {
+        self.checker.functions()
+    }
+
+    pub fn func_list(&self) -> &FuncDic {
+        &self.func_list
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Interpreter {
+        Interpreter::new()
     }
 }
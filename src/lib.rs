@@ -0,0 +1,21 @@
+extern crate pest;
+#[macro_use]
+extern crate pest_derive;
+
+#[macro_use]
+extern crate lazy_static;
+
+pub mod ast;
+pub mod checker;
+mod exhaustive;
+pub mod interpreter;
+pub mod parser;
+mod resolver;
+
+pub use ast::{Pattern, PistoletAST, PistoletExpr, PistoletValue, Span, Type, TypeDef};
+pub use checker::{check_program, Checker, TypeErr, TypeWarning};
+pub use interpreter::{
+    Closure, Fields, FuncDic, Interpreter, Parts, ProgStates, RunErr, RuntimeErr, ValueBind,
+    VarValue, DEFAULT_MAX_DEPTH,
+};
+pub use parser::{parse_expr_to_ast, parse_to_ast, ParseError};
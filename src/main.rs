@@ -1,37 +1,233 @@
-include!("parser.rs");
-include!("interpreter.rs");
+mod repl;
 
-use colored::*;
+use pistolet::{
+    parse_expr_to_ast, parse_to_ast, Checker, Interpreter, PistoletAST, RunErr, TypeErr,
+    TypeWarning, ValueBind, VarValue, DEFAULT_MAX_DEPTH,
+};
+use repl::Repl;
+use std::env;
 use std::fs;
+use std::process;
+
+/* exit codes follow sysexits.h so scripts can tell our failures from the program's own */
+const EXIT_USAGE: i32 = 64;
+const EXIT_DATAERR: i32 = 65;
+pub(crate) const EXIT_NOINPUT: i32 = 66;
+const EXIT_SOFTWARE: i32 = 70;
+
+const USAGE: &str = "usage: pistolet <command> [args]
+
+commands:
+    run <file>        run a program, exiting with the value of its top-level `return`,
+                      an int from 0 to 255
+        --state       print the global variables when the program finishes
+        --max-depth <n>
+                      allow <n> function calls active at once (default 10000)
+    check <file>      parse and type-check a program without running it, exiting
+                      with 65 on a syntax or type error
+    infer <file>      print the inferred signature of every function
+    ast <file>        print the syntax tree of a program
+    eval -e <expr>    evaluate a single expression
+    repl              start an interactive session";
 
 fn main() {
-    println!("{}", "[Test] Parser Test Begin!".green());
-    let file_path = "src/test/test_parser.pst";
-    match parse_to_ast(&fs::read_to_string(file_path).expect("cannot read file")) {
-        Ok(_) => println!("{}", "[Test] Parser Test Passed!".green()),
-        Err(_) => {
-            println!("{}", "[Failed] Parser Test Failed!".red());
-            return;
-        }
-    }
-    println!("{}", "[Test] Interpreter Test Begin!".green());
-        let file_path = "src/test/test_eval.pst";
-        let eval_result;
-        let unparsed_file = fs::read_to_string(file_path).expect("cannot read file");
-        let state = ProgStates::new();
-        let mut func_list = FuncDic::new();
-        eval_result = ast_eval(parse_to_ast(&unparsed_file).unwrap(), state, &mut func_list);
-
-        match eval_result {
-            Ok((result, _)) => result.print(),
-            Err(err_code) => match err_code {
-                RuntimeErr::ReturnValue(expr_value) => println!(
-                    "[Return] Exit with {} : {}",
-                    expr_value.get_type(),
-                    expr_value.get_value()
-                ),
-                _ => err_code.print(),
-            },
-        }
-    println!("{}", "[Test] Interpreter Test Passed!".green());
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let code = match args.as_slice() {
+        ["run", options @ ..] => match run_options(options) {
+            Some(options) => run_file(&options),
+            None => {
+                eprintln!("{}", USAGE);
+                EXIT_USAGE
+            }
+        },
+        ["check", file_path] => check_file(file_path),
+        ["infer", file_path] => infer_file(file_path),
+        ["ast", file_path] => ast_file(file_path),
+        ["eval", "-e", expr] => eval_expr(expr),
+        ["repl"] => Repl::new().run(),
+        ["help"] | ["-h"] | ["--help"] => {
+            println!("{}", USAGE);
+            0
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            EXIT_USAGE
+        }
+    };
+    process::exit(code);
+}
+
+fn read_program(file_path: &str) -> Result<PistoletAST, i32> {
+    let unparsed_file = fs::read_to_string(file_path).map_err(|err| {
+        eprintln!("[Error] cannot read {}: {}", file_path, err);
+        EXIT_NOINPUT
+    })?;
+    parse_to_ast(&unparsed_file).map_err(|err| {
+        eprintln!("{}: {}", file_path, err);
+        EXIT_DATAERR
+    })
+}
+
+fn print_type_errors(file_path: &str, errors: &[TypeErr]) {
+    for err in errors {
+        eprintln!("{}: {}", file_path, err);
+    }
+}
+
+fn print_warnings(file_path: &str, warnings: &[TypeWarning]) {
+    for warning in warnings {
+        eprintln!("{}: {}", file_path, warning);
+    }
+}
+
+/*
+ * A top-level `return` of an int becomes the exit code, any other value exits with 0.
+ * The system keeps only 0 to 255, anything else would reach the caller truncated.
+ */
+fn exit_code(value: &ValueBind) -> i32 {
+    match value.get_value() {
+        VarValue::Int(n @ 0..=255) => n as i32,
+        VarValue::Int(n) => {
+            eprintln!("[Error] cannot exit with {}, an exit code is from 0 to 255", n);
+            EXIT_SOFTWARE
+        }
+        _ => 0,
+    }
+}
+
+struct RunOptions<'a> {
+    file_path: &'a str,
+    print_state: bool,
+    max_depth: usize,
+}
+
+/* the file to run and the flags around it, in any order */
+fn run_options<'a>(args: &[&'a str]) -> Option<RunOptions<'a>> {
+    let mut file_path = None;
+    let mut print_state = false;
+    let mut max_depth = DEFAULT_MAX_DEPTH;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "--state" => print_state = true,
+            "--max-depth" => max_depth = args.next()?.parse().ok()?,
+            _ if file_path.is_none() => file_path = Some(*arg),
+            _ => return None,
+        }
+    }
+    Some(RunOptions {
+        file_path: file_path?,
+        print_state,
+        max_depth,
+    })
+}
+
+fn run_file(options: &RunOptions) -> i32 {
+    let file_path = options.file_path;
+    let ast = match read_program(file_path) {
+        Ok(ast) => ast,
+        Err(code) => return code,
+    };
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_depth(options.max_depth);
+    if let Err(errors) = interpreter.check(&ast) {
+        print_type_errors(file_path, &errors);
+        return EXIT_DATAERR;
+    }
+    print_warnings(file_path, interpreter.warnings());
+    let code = match interpreter.run(ast) {
+        Ok(None) => 0,
+        Ok(Some(expr_value)) => exit_code(&expr_value),
+        Err(RunErr::Unresolved(errors)) => {
+            print_type_errors(file_path, &errors);
+            EXIT_DATAERR
+        }
+        Err(RunErr::Runtime(err_code)) => {
+            err_code.print();
+            EXIT_SOFTWARE
+        }
+    };
+    if options.print_state {
+        interpreter.state().print();
+    }
+    code
+}
+
+fn check_file(file_path: &str) -> i32 {
+    let mut checker = Checker::new();
+    match read_program(file_path) {
+        Ok(ast) => match checker.check(&ast) {
+            Ok(()) => {
+                print_warnings(file_path, checker.warnings());
+                0
+            }
+            Err(errors) => {
+                print_type_errors(file_path, &errors);
+                EXIT_DATAERR
+            }
+        },
+        Err(code) => code,
+    }
+}
+
+fn infer_file(file_path: &str) -> i32 {
+    let ast = match read_program(file_path) {
+        Ok(ast) => ast,
+        Err(code) => return code,
+    };
+    let mut checker = Checker::new();
+    match checker.check(&ast) {
+        Ok(()) => {
+            print_warnings(file_path, checker.warnings());
+            for signature in checker.signatures() {
+                println!("{}", signature);
+            }
+            0
+        }
+        Err(errors) => {
+            print_type_errors(file_path, &errors);
+            EXIT_DATAERR
+        }
+    }
+}
+
+fn ast_file(file_path: &str) -> i32 {
+    match read_program(file_path) {
+        Ok(ast) => {
+            println!("{:#?}", ast);
+            0
+        }
+        Err(code) => code,
+    }
+}
+
+fn eval_expr(expr: &str) -> i32 {
+    let expr = match parse_expr_to_ast(expr) {
+        Ok(expr) => expr,
+        Err(err) => {
+            eprintln!("{}", err);
+            return EXIT_DATAERR;
+        }
+    };
+    let mut interpreter = Interpreter::new();
+    if let Err(errors) = interpreter.type_of(&expr) {
+        print_type_errors("-e", &errors);
+        return EXIT_DATAERR;
+    }
+    print_warnings("-e", interpreter.warnings());
+    match interpreter.eval_expr(expr) {
+        Ok(expr_value) => {
+            println!("{} : {}", expr_value.get_value(), expr_value.get_type());
+            0
+        }
+        Err(RunErr::Unresolved(errors)) => {
+            print_type_errors("-e", &errors);
+            EXIT_DATAERR
+        }
+        Err(RunErr::Runtime(err_code)) => {
+            err_code.print();
+            EXIT_SOFTWARE
+        }
+    }
 }
@@ -1,237 +1,881 @@
-include!("ast.rs");
-
-extern crate pest;
-#[macro_use]
-extern crate pest_derive;
-
-#[macro_use]
-extern crate lazy_static;
-
-use pest::error::Error;
-use pest::prec_climber::{Assoc, Operator, PrecClimber};
+use crate::ast::*;
+use pest::error::{ErrorVariant, LineColLocation};
+use pest::iterators::{Pair, Pairs};
+use pest::pratt_parser::{Assoc, Op, PrattParser};
 use pest::Parser;
+use std::rc::Rc;
 
 #[derive(Parser)]
 #[grammar = "pistolet.pest"]
 pub struct PistoletParser;
 
 lazy_static! {
-    static ref PREC_CLIMBER: PrecClimber<Rule> = {
+    static ref PRATT_PARSER: PrattParser<Rule> = {
         use Assoc::*;
         use Rule::*;
-        PrecClimber::new(vec![
-            Operator::new(add, Left) | Operator::new(sub, Left),
-            Operator::new(mul, Left) | Operator::new(div, Left),
-            Operator::new(and, Left) | Operator::new(or, Left) | Operator::new(nand, Left),
-            Operator::new(eq, Left) | Operator::new(lefteq, Left)|Operator::new(left, Left) |Operator::new(righteq, Left) | Operator::new(right, Left) ,
-        ])
+        /* loosest first */
+        PrattParser::new()
+            .op(Op::infix(or, Left))
+            .op(Op::infix(and, Left) | Op::infix(nand, Left))
+            .op(Op::infix(eq, Left)
+                | Op::infix(lefteq, Left)
+                | Op::infix(left, Left)
+                | Op::infix(righteq, Left)
+                | Op::infix(right, Left))
+            .op(Op::infix(add, Left) | Op::infix(sub, Left))
+            .op(Op::infix(mul, Left) | Op::infix(div, Left))
+            .op(Op::prefix(neg) | Op::prefix(not))
+            .op(Op::postfix(index) | Op::postfix(field) | Op::postfix(call))
     };
 }
 
-fn parse_to_ast(file: &str) -> Result<PistoletAST, Error<Rule>> {
-    let pistolet_prog = PistoletParser::parse(Rule::program, &file)
-        .expect("unsuccessful parse")
-        .next()
-        .unwrap();
-    use pest::iterators::Pair;
-    use pest::iterators::Pairs;
-
-    fn parse_value(pair: Pair<Rule>) -> PistoletValue {
-        match pair.as_rule() {
-            Rule::INTEGER => PistoletValue::Integer(pair.as_str().parse().unwrap()),
-            Rule::FLOAT => PistoletValue::Float(pair.as_str().parse().unwrap()),
-            Rule::BOOL => PistoletValue::Boolean(pair.as_str().parse().unwrap()),
-            Rule::VAR_NAME => PistoletValue::Var(pair.as_str().to_string()),
-            Rule::FUN_CALL => {
-                let mut new_pair = pair.into_inner();
-                PistoletValue::Funcall(
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::FUN_NAME => x.as_str().to_string(),
-                            _ => unreachable!(),
-                        }
-                    },
-                    new_pair.map(unwarp_expr).map(parse_expr).collect(),
-                )
-            }
-            _ => unreachable!(),
-        }
-    }
-
-    fn unwarp_expr(exp: Pair<Rule>) -> Pairs<Rule> {
-        exp.into_inner()
-    }
-
-    fn parse_expr(exp: Pairs<Rule>) -> PistoletExpr {
-        PREC_CLIMBER.climb(
-            exp,
-            |pair: Pair<Rule>| match pair.as_rule() {
-                Rule::VALUE => PistoletExpr::Val(parse_value(pair.into_inner().peek().unwrap())),
-                Rule::EXPR => parse_expr(pair.into_inner()),
-                Rule::BOOL_VALUE => {
-                    PistoletExpr::Val(parse_value(pair.into_inner().peek().unwrap()))
+#[derive(Debug, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub col: usize,
+    pub expected: Vec<String>,
+    pub message: String,
+    source_line: String,
+    width: usize,
+}
+
+impl ParseError {
+    fn from_pest(err: pest::error::Error<Rule>, input: &str) -> ParseError {
+        let ((line, col), width) = match err.line_col {
+            LineColLocation::Pos(pos) => (pos, 1),
+            LineColLocation::Span(start, end) => {
+                let width = if start.0 == end.0 { end.1 - start.1 } else { 1 };
+                (start, width)
+            }
+        };
+        let mut source_line = err.line().to_string();
+        let (mut line, mut col) = (line, col);
+        let (mut expected, mut unexpected) = match &err.variant {
+            ErrorVariant::ParsingError {
+                positives,
+                negatives,
+            } => (
+                positives.iter().map(rule_name).collect::<Vec<_>>(),
+                negatives.iter().map(rule_name).collect::<Vec<_>>(),
+            ),
+            ErrorVariant::CustomError { message } => {
+                return ParseError {
+                    line,
+                    col,
+                    expected: Vec::new(),
+                    message: message.clone(),
+                    source_line: source_line.trim_end_matches(&['\r', '\n'][..]).to_string(),
+                    width: width.max(1),
                 }
-                Rule::BOOL_EXPR => parse_expr(pair.into_inner()),
-                Rule::EXPR_NoTy => parse_expr(pair.into_inner()),
-                Rule::EQ_EXPR => parse_expr(pair.into_inner()),
-                _ => {
-                    println!("{:#?}", pair);
-                    unreachable!()
+            }
+        };
+        /* the rules expected only say where the last rule started, the attempted tokens where parsing stopped */
+        if let Some(attempts) = err.parse_attempts() {
+            if let Some(pos) = pest::Position::new(input, attempts.max_position) {
+                if pos.line_col() > (line, col) && !attempts.expected_tokens().is_empty() {
+                    let (l, c) = pos.line_col();
+                    line = l;
+                    col = c;
+                    source_line = pos.line_of().to_string();
+                    expected = token_names(attempts.expected_tokens());
+                    unexpected = token_names(attempts.unexpected_tokens());
+                } else if pos.line_col() == (line, col) {
+                    /* no rule is a closing bracket, only the attempted tokens know one could come next */
+                    let closing = token_names(attempts.expected_tokens())
+                        .into_iter()
+                        .filter(|token| ["`}`", "`)`", "`]`"].contains(&token.as_str()));
+                    expected.extend(closing);
                 }
-            },
-            |lhs: PistoletExpr, op: Pair<Rule>, rhs: PistoletExpr| match op.as_rule() {
-                Rule::and => PistoletExpr::And(Box::new(lhs), Box::new(rhs)),
-                Rule::or => PistoletExpr::Orb(Box::new(lhs), Box::new(rhs)),
-                Rule::nand => PistoletExpr::Nand(Box::new(lhs), Box::new(rhs)),
-                Rule::add => PistoletExpr::Add(Box::new(lhs), Box::new(rhs)),
-                Rule::sub => PistoletExpr::Sub(Box::new(lhs), Box::new(rhs)),
-                Rule::mul => PistoletExpr::Mul(Box::new(lhs), Box::new(rhs)),
-                Rule::div => PistoletExpr::Div(Box::new(lhs), Box::new(rhs)),
-                Rule::eq => PistoletExpr::Eq(Box::new(lhs), Box::new(rhs)),
-                Rule::lefteq => PistoletExpr::Leq(Box::new(lhs), Box::new(rhs)),
-                Rule::righteq => PistoletExpr::Req(Box::new(lhs), Box::new(rhs)),
-                Rule::left => PistoletExpr::Left(Box::new(lhs), Box::new(rhs)),
-                Rule::right => PistoletExpr::Right(Box::new(lhs), Box::new(rhs)),
-                _ => unreachable!(),
-            },
+            }
+        }
+        expected.dedup();
+        unexpected.dedup();
+        let message = match (expected.is_empty(), unexpected.is_empty()) {
+            (false, true) => format!("expected {}", expected.join(", ")),
+            (true, false) => format!("unexpected {}", unexpected.join(", ")),
+            (false, false) => format!(
+                "unexpected {}, expected {}",
+                unexpected.join(", "),
+                expected.join(", ")
+            ),
+            (true, true) => "unknown parsing error".to_string(),
+        };
+        ParseError {
+            line,
+            col,
+            expected,
+            message,
+            source_line: source_line.trim_end_matches(&['\r', '\n'][..]).to_string(),
+            width: width.max(1),
+        }
+    }
+
+    fn at(pair: &Pair<Rule>, expected: &[&str], message: &str) -> ParseError {
+        let span = pair.as_span();
+        let (line, col) = span.start_pos().line_col();
+        let source_line = span
+            .start_pos()
+            .line_of()
+            .trim_end_matches(&['\r', '\n'][..])
+            .to_string();
+        let width = span
+            .as_str()
+            .lines()
+            .next()
+            .map_or(1, |first| first.chars().count());
+        ParseError {
+            line,
+            col,
+            expected: expected.iter().map(|s| s.to_string()).collect(),
+            message: message.to_string(),
+            source_line,
+            width: width.max(1),
+        }
+    }
+
+    fn expected(pair: &Pair<Rule>, expected: &str) -> ParseError {
+        ParseError::at(
+            pair,
+            &[expected],
+            &format!("expected {}, found {}", expected, rule_name(&pair.as_rule())),
+        )
+    }
+
+    pub fn snippet(&self) -> String {
+        let gutter = self.line.to_string().len();
+        let padding: String = self
+            .source_line
+            .chars()
+            .take(self.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        format!(
+            "{:w$} |\n{} | {}\n{:w$} | {}{}",
+            "",
+            self.line,
+            self.source_line,
+            "",
+            padding,
+            "^".repeat(self.width),
+            w = gutter
         )
     }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "[Error] line {}, column {}: {}",
+            self.line, self.col, self.message
+        )?;
+        write!(f, "{}", self.snippet())
+    }
+}
+
+fn token_names<T: ToString>(tokens: Vec<T>) -> Vec<String> {
+    tokens
+        .iter()
+        .map(|token| token.to_string())
+        .filter(|token| !token.trim().is_empty() && token != "(*")
+        .map(|token| format!("`{}`", token))
+        .collect()
+}
+
+fn rule_name(rule: &Rule) -> String {
+    match rule {
+        Rule::EOI => "end of input",
+        Rule::expression => "expression",
+        Rule::sentence | Rule::TERM => "statement",
+        Rule::PrintLine => "`println`",
+        Rule::FUN_CALL => "function call",
+        Rule::EXPR => "expression",
+        Rule::NONE => "nothing",
+        Rule::VALUE | Rule::PAREN => "value",
+        Rule::PARA_LIST => "parameter list",
+        Rule::VAR_BIND => "parameter",
+        Rule::RETURN => "`return`",
+        Rule::BREAK => "`break`",
+        Rule::CONTINUE => "`continue`",
+        Rule::LABEL => "loop label",
+        Rule::FUN => "`fun`",
+        Rule::LET => "`let`",
+        Rule::MUT => "`mut`",
+        Rule::WHILE => "`loop`",
+        Rule::WHILE_DO => "`while`",
+        Rule::FOR => "`for`",
+        Rule::IF => "`if`",
+        Rule::BOOL => "boolean",
+        Rule::FUN_NAME => "function name",
+        Rule::TYPE_NAME => "type name",
+        Rule::TYPE | Rule::ARRAY_TYPE | Rule::TUPLE_TYPE => "type",
+        Rule::ARRAY => "array",
+        Rule::TUPLE => "tuple",
+        Rule::TUPLE_PAT => "pattern",
+        Rule::TYPE_DEF => "`type`",
+        Rule::RECORD_TYPE => "record type",
+        Rule::FIELD_DECL => "field declaration",
+        Rule::RECORD => "record",
+        Rule::UPDATE => "record update",
+        Rule::FIELD_INIT => "field",
+        Rule::FIELD_NAME => "field name",
+        Rule::field => "field access",
+        Rule::call => "call",
+        Rule::LAMBDA => "`fun`",
+        Rule::VARIANT_TYPE | Rule::VARIANT => "constructor",
+        Rule::CTOR_NAME => "constructor name",
+        Rule::MATCH | Rule::MATCH_EXPR => "`match`",
+        Rule::ARM | Rule::ARM_EXPR => "match arm",
+        Rule::GUARD => "`when`",
+        Rule::CTOR_PAT
+        | Rule::MATCH_TUPLE_PAT
+        | Rule::WILD_PAT
+        | Rule::LIT_PAT
+        | Rule::CONST_PAT
+        | Rule::BIND_PAT => "pattern",
+        Rule::index => "index",
+        Rule::ASSIGN => "assignment",
+        Rule::CALL => "function call",
+        Rule::VAR_NAME => "variable name",
+        Rule::FLOAT => "float",
+        Rule::STRING => "string",
+        Rule::INTEGER | Rule::int => "integer",
+        Rule::add
+        | Rule::sub
+        | Rule::mul
+        | Rule::div
+        | Rule::and
+        | Rule::or
+        | Rule::nand
+        | Rule::eq
+        | Rule::lefteq
+        | Rule::righteq
+        | Rule::left
+        | Rule::right => "operator",
+        Rule::neg | Rule::not => "prefix operator",
+        _ => return format!("{:?}", rule),
+    }
+    .to_string()
+}
+
+fn span_of(pair: &Pair<Rule>) -> Span {
+    let span = pair.as_span();
+    let (line, col) = span.start_pos().line_col();
+    Span::new(span.start(), span.end(), line, col)
+}
 
-    fn parse_prog(pair: Pair<Rule>) -> PistoletAST {
-        match pair.as_rule() {
-            Rule::program => PistoletAST::Seq(pair.into_inner().map(parse_prog).collect()),
-            Rule::TERM => PistoletAST::Seq(pair.into_inner().map(parse_prog).collect()),
-            Rule::sentence => parse_prog(pair.into_inner().peek().unwrap()),
-            Rule::PARA_LIST => PistoletAST::Paralist(pair.into_inner().map(parse_prog).collect()),
-            Rule::LET => {
-                let mut new_pair = pair.into_inner();
-                PistoletAST::Let(
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::VAR_NAME => x.as_str().to_string(),
-                            Rule::TYPE_NAME => x.as_str().to_string(),
-                            _ => unreachable!(),
-                        }
-                    },
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::VAR_NAME => x.as_str().to_string(),
-                            Rule::TYPE_NAME => x.as_str().to_string(),
-                            _ => unreachable!(),
-                        }
-                    },
-                    match new_pair.peek().unwrap().as_rule() {
-                        Rule::EXPR_NoTy => parse_expr(new_pair.peek().unwrap().into_inner()),
-                        _ => unreachable!(),
-                    },
-                )
-            }
-            Rule::IF => {
-                let mut new_pair = pair.into_inner();
-                PistoletAST::If(
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::BOOL_EXPR => parse_expr(x.into_inner()),
-                            _ => unreachable!(),
-                        }
-                    },
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::TERM => Box::new(parse_prog(x)),
-                            _ => unreachable!(),
-                        }
-                    },
-                    match new_pair.peek().unwrap().as_rule() {
-                        Rule::TERM => Box::new(parse_prog(new_pair.peek().unwrap())),
-                        _ => unreachable!(),
-                    },
-                )
-            }
-            Rule::WHILE => {
-                let mut new_pair = pair.into_inner();
-                PistoletAST::While(
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::TERM => Box::new(parse_prog(x)),
-                            _ => unreachable!(),
-                        }
-                    },
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::BOOL_EXPR => parse_expr(x.into_inner()),
-                            _ => unreachable!(),
-                        }
-                    },
-                )
-            }
-            Rule::RETURN => PistoletAST::Return(parse_expr(pair.into_inner())),
-            Rule::VAR_BIND => {
-                let mut new_pair = pair.into_inner();
-                PistoletAST::Varbind(
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::VAR_NAME => x.as_str().to_string(),
-                            _ => unreachable!(),
-                        }
-                    },
-                    match new_pair.peek().unwrap().as_rule() {
-                        Rule::TYPE_NAME => new_pair.as_str().to_string(),
-                        _ => unreachable!(),
-                    },
-                )
-            }
-            Rule::FUN => {
-                let mut new_pair = pair.into_inner();
-                PistoletAST::Fun(
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::FUN_NAME => x.as_str().to_string(),
-                            _ => unreachable!(),
-                        }
-                    },
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::PARA_LIST => Box::new(parse_prog(x)),
-                            _ => unreachable!(),
-                        }
-                    },
-                    {
-                        let x = new_pair.next().unwrap();
-                        match x.as_rule() {
-                            Rule::TYPE_NAME => x.as_str().to_string(),
-                            _ => unreachable!(),
-                        }
-                    },
-                    match new_pair.peek().unwrap().as_rule() {
-                        Rule::TERM => Box::new(parse_prog(new_pair.peek().unwrap())),
-                        _ => unreachable!(),
-                    },
-                )
-            }
-            Rule::EOI => PistoletAST::EOI,
-            Rule::PrintLine => {
-                let new_pair = pair.into_inner();
-                match new_pair.peek().unwrap().as_rule() {
-                    Rule::EXPR_NoTy => PistoletAST::PrintLine(parse_expr(new_pair)),
-                    _ => unreachable!(),
+pub fn parse_to_ast(file: &str) -> Result<PistoletAST, ParseError> {
+    pest::set_error_detail(true);
+    let pistolet_prog = PistoletParser::parse(Rule::program, file)
+        .map_err(|err| ParseError::from_pest(err, file))?
+        .next()
+        .unwrap();
+    parse_prog(pistolet_prog)
+}
+
+pub fn parse_expr_to_ast(expr: &str) -> Result<PistoletExpr, ParseError> {
+    pest::set_error_detail(true);
+    let pistolet_expr = PistoletParser::parse(Rule::expression, expr)
+        .map_err(|err| ParseError::from_pest(err, expr))?
+        .next()
+        .unwrap();
+    let parent = pistolet_expr.clone();
+    parse_expr_pair(next_pair(
+        &mut pistolet_expr.into_inner(),
+        &parent,
+        "expression",
+    )?)
+}
+
+fn next_pair<'a>(
+    pairs: &mut Pairs<'a, Rule>,
+    parent: &Pair<'a, Rule>,
+    expected: &str,
+) -> Result<Pair<'a, Rule>, ParseError> {
+    pairs
+        .next()
+        .ok_or_else(|| ParseError::at(parent, &[expected], &format!("missing {}", expected)))
+}
+
+fn expect_name(pair: Pair<Rule>, rule: Rule) -> Result<String, ParseError> {
+    if pair.as_rule() == rule {
+        Ok(pair.as_str().to_string())
+    } else {
+        Err(ParseError::expected(&pair, &rule_name(&rule)))
+    }
+}
+
+/* a loop label, without its quote */
+fn optional_label(pairs: &mut Pairs<Rule>) -> Option<String> {
+    match pairs.peek() {
+        Some(pair) if pair.as_rule() == Rule::LABEL => {
+            pairs.next();
+            Some(pair.as_str()[1..].to_string())
+        }
+        _ => None,
+    }
+}
+
+fn optional_type(pairs: &mut Pairs<Rule>) -> Result<Option<Type>, ParseError> {
+    match pairs.peek() {
+        Some(pair) if pair.as_rule() == Rule::TYPE => {
+            pairs.next();
+            parse_type(pair).map(Some)
+        }
+        _ => Ok(None),
+    }
+}
+
+fn parse_type(pair: Pair<Rule>) -> Result<Type, ParseError> {
+    let parent = pair.clone();
+    match pair.as_rule() {
+        Rule::TYPE => {
+            let mut types = pair
+                .into_inner()
+                .map(parse_type)
+                .collect::<Result<Vec<_>, _>>()?;
+            match types.pop() {
+                Some(ret) if types.is_empty() => Ok(ret),
+                Some(ret) => Ok(Type::Fun(types, Box::new(ret))),
+                None => Err(ParseError::expected(&parent, "type")),
+            }
+        }
+        Rule::ARRAY_TYPE => Ok(Type::Array(Box::new(parse_type(next_pair(
+            &mut pair.into_inner(),
+            &parent,
+            "element type",
+        )?)?))),
+        Rule::TUPLE_TYPE => Ok(Type::Tuple(
+            pair.into_inner().map(parse_type).collect::<Result<_, _>>()?,
+        )),
+        Rule::TYPE_NAME => Ok(Type::from_name(pair.as_str())),
+        _ => Err(ParseError::expected(&pair, "type")),
+    }
+}
+
+/* `name = expr` pairs of a record literal or update */
+fn parse_fields(pairs: Pairs<Rule>) -> Result<Vec<(String, PistoletExpr)>, ParseError> {
+    let mut fields = Vec::new();
+    for pair in pairs {
+        let parent = pair.clone();
+        let mut new_pair = pair.into_inner();
+        let name = expect_name(
+            next_pair(&mut new_pair, &parent, "field name")?,
+            Rule::FIELD_NAME,
+        )?;
+        let expr = parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+        fields.push((name, expr));
+    }
+    Ok(fields)
+}
+
+fn parse_pattern(pair: Pair<Rule>) -> Result<Pattern, ParseError> {
+    let span = span_of(&pair);
+    match pair.as_rule() {
+        Rule::VAR_NAME => Ok(Pattern::Var(pair.as_str().into(), 0, span)),
+        Rule::TUPLE_PAT => Ok(Pattern::Tuple(
+            pair.into_inner()
+                .map(parse_pattern)
+                .collect::<Result<_, _>>()?,
+            span,
+        )),
+        _ => Err(ParseError::expected(&pair, "variable name")),
+    }
+}
+
+fn parse_match_pattern(pair: Pair<Rule>) -> Result<Pattern, ParseError> {
+    let parent = pair.clone();
+    let span = span_of(&pair);
+    match pair.as_rule() {
+        Rule::BIND_PAT => Ok(Pattern::Var(pair.as_str().into(), 0, span)),
+        Rule::WILD_PAT => Ok(Pattern::Wildcard(span)),
+        Rule::CONST_PAT => Ok(Pattern::Ctor(pair.as_str().to_string(), None, span)),
+        Rule::MATCH_TUPLE_PAT => Ok(Pattern::Tuple(
+            pair.into_inner()
+                .map(parse_match_pattern)
+                .collect::<Result<_, _>>()?,
+            span,
+        )),
+        Rule::CTOR_PAT => {
+            let mut new_pair = pair.into_inner();
+            let ctor = expect_name(
+                next_pair(&mut new_pair, &parent, "constructor name")?,
+                Rule::CTOR_NAME,
+            )?;
+            let arg = match new_pair.next() {
+                Some(arg) => Some(Box::new(parse_match_pattern(arg)?)),
+                None => None,
+            };
+            Ok(Pattern::Ctor(ctor, arg, span))
+        }
+        Rule::LIT_PAT => {
+            let mut new_pair = pair.into_inner();
+            let first = next_pair(&mut new_pair, &parent, "literal")?;
+            match first.as_rule() {
+                Rule::neg => {
+                    let literal = next_pair(&mut new_pair, &parent, "number")?;
+                    match parse_value(literal)? {
+                        PistoletValue::Integer(n, _) => Ok(Pattern::Literal(PistoletValue::Integer(-n, span))),
+                        PistoletValue::Float(n, _) => Ok(Pattern::Literal(PistoletValue::Float(-n, span))),
+                        _ => Err(ParseError::expected(&parent, "number")),
+                    }
                 }
+                _ => Ok(Pattern::Literal(parse_value(first)?)),
             }
-            _ => {
-                println!("{:#?}", pair);
-                unimplemented!()
+        }
+        _ => Err(ParseError::expected(&pair, "pattern")),
+    }
+}
+
+/* `pattern (when guard)? -> body` for both forms of `match` */
+fn parse_arm<T>(
+    pair: Pair<Rule>,
+    parse_body: fn(Pair<Rule>) -> Result<T, ParseError>,
+) -> Result<(Pattern, Option<PistoletExpr>, T), ParseError> {
+    let parent = pair.clone();
+    let mut new_pair = pair.into_inner();
+    let pattern = parse_match_pattern(next_pair(&mut new_pair, &parent, "pattern")?)?;
+    let mut body = next_pair(&mut new_pair, &parent, "`->`")?;
+    let mut guard = None;
+    if body.as_rule() == Rule::GUARD {
+        let guard_parent = body.clone();
+        guard = Some(parse_expr_pair(next_pair(
+            &mut body.into_inner(),
+            &guard_parent,
+            "guard",
+        )?)?);
+        body = next_pair(&mut new_pair, &parent, "`->`")?;
+    }
+    Ok((pattern, guard, parse_body(body)?))
+}
+
+fn parse_value(pair: Pair<Rule>) -> Result<PistoletValue, ParseError> {
+    let span = span_of(&pair);
+    match pair.as_rule() {
+        Rule::INTEGER => pair
+            .as_str()
+            .parse()
+            .map(|n| PistoletValue::Integer(n, span))
+            .map_err(|_| ParseError::at(&pair, &[], "integer literal out of range")),
+        Rule::FLOAT => pair
+            .as_str()
+            .parse()
+            .map(|n| PistoletValue::Float(n, span))
+            .map_err(|_| ParseError::at(&pair, &[], "invalid float literal")),
+        Rule::BOOL => Ok(PistoletValue::Boolean(pair.as_str() == "true", span)),
+        Rule::STRING => unescape(&pair).map(|s| PistoletValue::Str(s, span)),
+        Rule::ARRAY => Ok(PistoletValue::Array(
+            pair.into_inner()
+                .map(parse_expr_pair)
+                .collect::<Result<_, _>>()?,
+            span,
+        )),
+        Rule::TUPLE => Ok(PistoletValue::Tuple(
+            pair.into_inner()
+                .map(parse_expr_pair)
+                .collect::<Result<_, _>>()?,
+            span,
+        )),
+        Rule::RECORD => {
+            let parent = pair.clone();
+            let mut new_pair = pair.into_inner();
+            let type_name = expect_name(
+                next_pair(&mut new_pair, &parent, "type name")?,
+                Rule::TYPE_NAME,
+            )?;
+            Ok(PistoletValue::Record(type_name, parse_fields(new_pair)?, span))
+        }
+        Rule::VAR_NAME => Ok(PistoletValue::Var(pair.as_str().to_string(), None, span)),
+        Rule::FUN_CALL => {
+            let parent = pair.clone();
+            let mut new_pair = pair.into_inner();
+            let func_name = expect_name(
+                next_pair(&mut new_pair, &parent, "function name")?,
+                Rule::FUN_NAME,
+            )?;
+            let mut args = Vec::new();
+            for arg in new_pair {
+                args.push(parse_expr_pair(arg)?);
             }
+            Ok(PistoletValue::Funcall(func_name, args, span))
+        }
+        _ => Err(ParseError::expected(&pair, "value")),
+    }
+}
+
+/* the literal without its quotes, with \n \t \r \0 \\ \" and \u{...} decoded */
+fn unescape(pair: &Pair<Rule>) -> Result<String, ParseError> {
+    let literal = pair.as_str();
+    let mut chars = literal[1..literal.len() - 1].chars();
+    let mut result = String::new();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
         }
+        let decoded = match chars.next() {
+            Some('n') => Some('\n'),
+            Some('t') => Some('\t'),
+            Some('r') => Some('\r'),
+            Some('0') => Some('\0'),
+            Some('\\') => Some('\\'),
+            Some('"') => Some('"'),
+            Some('u') => {
+                let rest = chars.as_str();
+                let code = rest
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .and_then(|(hex, _)| u32::from_str_radix(hex, 16).ok())
+                    .and_then(char::from_u32);
+                if code.is_some() {
+                    let close = rest.find('}').unwrap();
+                    chars = rest[close + 1..].chars();
+                }
+                code
+            }
+            _ => None,
+        };
+        match decoded {
+            Some(c) => result.push(c),
+            None => return Err(ParseError::at(pair, &[], "invalid escape sequence in string")),
+        }
+    }
+    Ok(result)
+}
+
+fn parse_expr_pair(pair: Pair<Rule>) -> Result<PistoletExpr, ParseError> {
+    match pair.as_rule() {
+        Rule::EXPR => parse_expr(pair.into_inner()),
+        _ => Err(ParseError::expected(&pair, "expression")),
+    }
+}
+
+fn parse_expr(exp: Pairs<Rule>) -> Result<PistoletExpr, ParseError> {
+    PRATT_PARSER
+        .map_primary(|pair: Pair<Rule>| match pair.as_rule() {
+            Rule::VALUE => {
+                let parent = pair.clone();
+                let value = next_pair(&mut pair.into_inner(), &parent, "value")?;
+                let span = span_of(&value);
+                if value.as_rule() == Rule::LAMBDA {
+                    let mut new_pair = value.into_inner();
+                    let para_list = parse_prog(next_pair(&mut new_pair, &parent, "parameter list")?)?;
+                    let return_type = optional_type(&mut new_pair)?;
+                    let body = parse_block(next_pair(&mut new_pair, &parent, "`{`")?)?;
+                    return Ok(PistoletExpr::Lambda(
+                        Box::new(para_list),
+                        return_type,
+                        Rc::new(body),
+                        span,
+                    ));
+                }
+                if value.as_rule() == Rule::MATCH_EXPR {
+                    let mut new_pair = value.into_inner();
+                    let scrutinee =
+                        parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+                    let arms = new_pair
+                        .map(|arm| parse_arm(arm, parse_expr_pair))
+                        .collect::<Result<_, _>>()?;
+                    return Ok(PistoletExpr::Match(Box::new(scrutinee), arms, span));
+                }
+                if value.as_rule() != Rule::UPDATE {
+                    return Ok(PistoletExpr::Val(parse_value(value)?));
+                }
+                let mut new_pair = value.into_inner();
+                let record = parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+                Ok(PistoletExpr::Update(
+                    Box::new(record),
+                    parse_fields(new_pair)?,
+                    span,
+                ))
+            }
+            Rule::PAREN => {
+                let parent = pair.clone();
+                let mut expr = parse_expr_pair(next_pair(&mut pair.into_inner(), &parent, "expression")?)?;
+                expr.set_span(span_of(&parent));
+                Ok(expr)
+            }
+            _ => parse_expr_pair(pair),
+        })
+        .map_prefix(|op: Pair<Rule>, rhs| {
+            let rhs = rhs?;
+            let span = span_of(&op).merge(rhs.span());
+            match (op.as_rule(), rhs) {
+                /* `-2` is a literal rather than a negation, as it was before unary minus */
+                (Rule::neg, PistoletExpr::Val(PistoletValue::Integer(n, _))) => {
+                    Ok(PistoletExpr::Val(PistoletValue::Integer(-n, span)))
+                }
+                (Rule::neg, PistoletExpr::Val(PistoletValue::Float(n, _))) => {
+                    Ok(PistoletExpr::Val(PistoletValue::Float(-n, span)))
+                }
+                (Rule::neg, rhs) => Ok(PistoletExpr::Neg(Box::new(rhs), span)),
+                (Rule::not, rhs) => Ok(PistoletExpr::Not(Box::new(rhs), span)),
+                _ => Err(ParseError::expected(&op, "prefix operator")),
+            }
+        })
+        .map_postfix(|lhs, op: Pair<Rule>| {
+            let lhs = lhs?;
+            let span = lhs.span().merge(span_of(&op));
+            let parent = op.clone();
+            let rule = op.as_rule();
+            if rule == Rule::call {
+                let args = op
+                    .into_inner()
+                    .map(parse_expr_pair)
+                    .collect::<Result<_, _>>()?;
+                return Ok(PistoletExpr::Call(Box::new(lhs), args, span));
+            }
+            let inner = next_pair(&mut op.into_inner(), &parent, "index")?;
+            match rule {
+                Rule::field => Ok(PistoletExpr::Field(
+                    Box::new(lhs),
+                    expect_name(inner, Rule::FIELD_NAME)?,
+                    span,
+                )),
+                _ => Ok(PistoletExpr::Index(
+                    Box::new(lhs),
+                    Box::new(parse_expr_pair(inner)?),
+                    span,
+                )),
+            }
+        })
+        .map_infix(|lhs, op: Pair<Rule>, rhs| {
+            let (lhs, rhs) = (lhs?, rhs?);
+            let span = lhs.span().merge(rhs.span());
+            let (lhs, rhs) = (Box::new(lhs), Box::new(rhs));
+            match op.as_rule() {
+                Rule::and => Ok(PistoletExpr::And(lhs, rhs, span)),
+                Rule::or => Ok(PistoletExpr::Orb(lhs, rhs, span)),
+                Rule::nand => Ok(PistoletExpr::Nand(lhs, rhs, span)),
+                Rule::add => Ok(PistoletExpr::Add(lhs, rhs, span)),
+                Rule::sub => Ok(PistoletExpr::Sub(lhs, rhs, span)),
+                Rule::mul => Ok(PistoletExpr::Mul(lhs, rhs, span)),
+                Rule::div => Ok(PistoletExpr::Div(lhs, rhs, span)),
+                Rule::eq => Ok(PistoletExpr::Eq(lhs, rhs, span)),
+                Rule::lefteq => Ok(PistoletExpr::Leq(lhs, rhs, span)),
+                Rule::righteq => Ok(PistoletExpr::Req(lhs, rhs, span)),
+                Rule::left => Ok(PistoletExpr::Left(lhs, rhs, span)),
+                Rule::right => Ok(PistoletExpr::Right(lhs, rhs, span)),
+                _ => Err(ParseError::expected(&op, "operator")),
+            }
+        })
+        .parse(exp)
+}
+
+fn parse_block(pair: Pair<Rule>) -> Result<PistoletAST, ParseError> {
+    match pair.as_rule() {
+        Rule::TERM => parse_prog(pair),
+        _ => Err(ParseError::expected(&pair, "`{`")),
     }
+}
 
-    Ok(parse_prog(pistolet_prog))
+fn parse_prog(pair: Pair<Rule>) -> Result<PistoletAST, ParseError> {
+    let parent = pair.clone();
+    let span = span_of(&pair);
+    match pair.as_rule() {
+        Rule::program | Rule::TERM => Ok(PistoletAST::Seq(
+            pair.into_inner()
+                .map(parse_prog)
+                .collect::<Result<_, _>>()?,
+            span,
+        )),
+        Rule::sentence => parse_prog(next_pair(&mut pair.into_inner(), &parent, "statement")?),
+        Rule::PARA_LIST => Ok(PistoletAST::Paralist(
+            pair.into_inner()
+                .map(parse_prog)
+                .collect::<Result<_, _>>()?,
+            span,
+        )),
+        Rule::TYPE_DEF => {
+            let mut new_pair = pair.into_inner();
+            let type_name = expect_name(
+                next_pair(&mut new_pair, &parent, "type name")?,
+                Rule::TYPE_NAME,
+            )?;
+            let body = next_pair(&mut new_pair, &parent, "record type")?;
+            if body.as_rule() == Rule::VARIANT_TYPE {
+                let mut ctors = Vec::new();
+                for variant in body.into_inner() {
+                    let variant_parent = variant.clone();
+                    let mut variant_pair = variant.into_inner();
+                    let ctor = expect_name(
+                        next_pair(&mut variant_pair, &variant_parent, "constructor name")?,
+                        Rule::CTOR_NAME,
+                    )?;
+                    let args = variant_pair.map(parse_type).collect::<Result<_, _>>()?;
+                    ctors.push((ctor, args));
+                }
+                return Ok(PistoletAST::TypeDef(type_name, TypeDef::Variant(ctors), span));
+            }
+            let mut fields = Vec::new();
+            for decl in body.into_inner() {
+                let decl_parent = decl.clone();
+                let mut decl_pair = decl.into_inner();
+                let name = expect_name(
+                    next_pair(&mut decl_pair, &decl_parent, "field name")?,
+                    Rule::FIELD_NAME,
+                )?;
+                let field_type = parse_type(next_pair(&mut decl_pair, &decl_parent, "type")?)?;
+                fields.push((name, field_type));
+            }
+            Ok(PistoletAST::TypeDef(type_name, TypeDef::Record(fields), span))
+        }
+        Rule::LET => {
+            let mut new_pair = pair.into_inner();
+            let mutable = match new_pair.peek() {
+                Some(next) if next.as_rule() == Rule::MUT => {
+                    new_pair.next();
+                    true
+                }
+                _ => false,
+            };
+            let pattern = parse_pattern(next_pair(&mut new_pair, &parent, "variable name")?)?;
+            let var_type = optional_type(&mut new_pair)?;
+            let var_expr = parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+            Ok(PistoletAST::Let(pattern, mutable, var_type, var_expr, span))
+        }
+        Rule::IF => {
+            let mut new_pair = pair.into_inner();
+            let cond = parse_expr_pair(next_pair(&mut new_pair, &parent, "condition")?)?;
+            let branch_true = parse_block(next_pair(&mut new_pair, &parent, "`{`")?)?;
+            let branch_false = parse_block(next_pair(&mut new_pair, &parent, "`{`")?)?;
+            Ok(PistoletAST::If(
+                cond,
+                Box::new(branch_true),
+                Box::new(branch_false),
+                span,
+            ))
+        }
+        Rule::MATCH => {
+            let mut new_pair = pair.into_inner();
+            let scrutinee = parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+            let arms = new_pair
+                .map(|arm| parse_arm(arm, parse_block))
+                .collect::<Result<_, _>>()?;
+            Ok(PistoletAST::Match(scrutinee, arms, span))
+        }
+        Rule::WHILE => {
+            let mut new_pair = pair.into_inner();
+            let label = optional_label(&mut new_pair);
+            let body = parse_block(next_pair(&mut new_pair, &parent, "`{`")?)?;
+            let cond = parse_expr_pair(next_pair(&mut new_pair, &parent, "condition")?)?;
+            Ok(PistoletAST::While(Box::new(body), cond, label, Vec::new(), span))
+        }
+        Rule::WHILE_DO => {
+            let mut new_pair = pair.into_inner();
+            let label = optional_label(&mut new_pair);
+            let cond = parse_expr_pair(next_pair(&mut new_pair, &parent, "condition")?)?;
+            let body = parse_block(next_pair(&mut new_pair, &parent, "`{`")?)?;
+            Ok(PistoletAST::WhileDo(cond, Box::new(body), label, span))
+        }
+        Rule::FOR => {
+            let mut new_pair = pair.into_inner();
+            let label = optional_label(&mut new_pair);
+            let var_name = expect_name(
+                next_pair(&mut new_pair, &parent, "variable name")?,
+                Rule::VAR_NAME,
+            )?;
+            let start = parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+            let mut end = None;
+            let mut body = None;
+            for next in new_pair {
+                if next.as_rule() == Rule::TERM {
+                    body = Some(parse_block(next)?);
+                } else {
+                    end = Some(parse_expr_pair(next)?);
+                }
+            }
+            match body {
+                Some(body) => Ok(PistoletAST::For(var_name.into(), start, end, Box::new(body), label, span)),
+                None => Err(ParseError::at(&parent, &["`{`"], "missing loop body")),
+            }
+        }
+        Rule::BREAK => Ok(PistoletAST::Break(optional_label(&mut pair.into_inner()), span)),
+        Rule::CONTINUE => Ok(PistoletAST::Continue(optional_label(&mut pair.into_inner()), span)),
+        Rule::RETURN => {
+            let mut new_pair = pair.into_inner();
+            let expr = parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+            Ok(PistoletAST::Return(expr, span))
+        }
+        Rule::VAR_BIND => {
+            let mut new_pair = pair.into_inner();
+            let var_name = expect_name(
+                next_pair(&mut new_pair, &parent, "variable name")?,
+                Rule::VAR_NAME,
+            )?;
+            let var_type = optional_type(&mut new_pair)?;
+            Ok(PistoletAST::Varbind(var_name.into(), var_type, span))
+        }
+        Rule::FUN => {
+            let mut new_pair = pair.into_inner();
+            let func_name = expect_name(
+                next_pair(&mut new_pair, &parent, "function name")?,
+                Rule::FUN_NAME,
+            )?;
+            let para_list = next_pair(&mut new_pair, &parent, "parameter list")?;
+            let para_list = match para_list.as_rule() {
+                Rule::PARA_LIST => parse_prog(para_list)?,
+                _ => return Err(ParseError::expected(&para_list, "parameter list")),
+            };
+            let return_type = optional_type(&mut new_pair)?;
+            let func_body = parse_block(next_pair(&mut new_pair, &parent, "`{`")?)?;
+            Ok(PistoletAST::Fun(
+                func_name,
+                Box::new(para_list),
+                return_type,
+                Rc::new(func_body),
+                span,
+            ))
+        }
+        Rule::EOI => Ok(PistoletAST::EOI),
+        Rule::PrintLine => {
+            let mut new_pair = pair.into_inner();
+            let expr = parse_expr_pair(next_pair(&mut new_pair, &parent, "expression")?)?;
+            Ok(PistoletAST::PrintLine(expr, span))
+        }
+        Rule::ASSIGN => {
+            let mut new_pair = pair.into_inner();
+            let var_pair = next_pair(&mut new_pair, &parent, "variable name")?;
+            let var_span = span_of(&var_pair);
+            let var_name = expect_name(var_pair, Rule::VAR_NAME)?;
+            let mut target = PistoletExpr::Val(PistoletValue::Var(var_name, None, var_span));
+            let mut value = None;
+            for next in new_pair {
+                if next.as_rule() == Rule::index {
+                    let index_span = target.span().merge(span_of(&next));
+                    let index_parent = next.clone();
+                    let index = parse_expr_pair(next_pair(
+                        &mut next.into_inner(),
+                        &index_parent,
+                        "index",
+                    )?)?;
+                    target = PistoletExpr::Index(Box::new(target), Box::new(index), index_span);
+                } else {
+                    value = Some(parse_expr_pair(next)?);
+                }
+            }
+            match value {
+                Some(value) => Ok(PistoletAST::Assign(target, value, span)),
+                None => Err(ParseError::at(&parent, &["expression"], "missing expression")),
+            }
+        }
+        Rule::CALL => {
+            let mut new_pair = pair.into_inner();
+            let mut call = PistoletExpr::Val(parse_value(next_pair(
+                &mut new_pair,
+                &parent,
+                "function call",
+            )?)?);
+            for next in new_pair {
+                let call_span = call.span().merge(span_of(&next));
+                let args = next
+                    .into_inner()
+                    .map(parse_expr_pair)
+                    .collect::<Result<_, _>>()?;
+                call = PistoletExpr::Call(Box::new(call), args, call_span);
+            }
+            Ok(PistoletAST::Call(call, span))
+        }
+        _ => Err(ParseError::expected(&pair, "statement")),
+    }
 }
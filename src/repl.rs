@@ -0,0 +1,197 @@
+use crate::EXIT_NOINPUT;
+use pistolet::{
+    parse_expr_to_ast, parse_to_ast, Interpreter, ParseError, PistoletAST, PistoletExpr, TypeErr,
+    TypeWarning,
+};
+use std::fs;
+use std::io::{self, BufRead, Write};
+
+const REPL_HELP: &str = "statements are run as they are entered, bare expressions are echoed
+
+    :state          print the global variables
+    :funcs          list the defined functions
+    :type <expr>    print the type of an expression
+    :load <file>    run a file in the current session
+    :help           show this message
+    :quit           leave the repl
+
+an unfinished `fun`, `loop` or `if` keeps reading lines, an empty line cancels it";
+
+pub struct Repl {
+    interpreter: Interpreter,
+    buffer: String,
+}
+
+enum ReplInput {
+    Expr(PistoletExpr),
+    Prog(PistoletAST),
+    Incomplete,
+    Invalid(ParseError),
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        Repl {
+            interpreter: Interpreter::new(),
+            buffer: String::new(),
+        }
+    }
+
+    pub fn run(&mut self) -> i32 {
+        println!("Pistolet REPL, type :help for help");
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("{}", if self.buffer.is_empty() { "pistolet> " } else { "        | " });
+            io::stdout().flush().ok();
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                Some(Err(err)) => {
+                    eprintln!("[Error] cannot read input: {}", err);
+                    return EXIT_NOINPUT;
+                }
+                None => {
+                    println!();
+                    return 0;
+                }
+            };
+            if self.buffer.is_empty() && line.trim_start().starts_with(':') {
+                if !self.command(line.trim()) {
+                    return 0;
+                }
+                continue;
+            }
+            let force = line.trim().is_empty();
+            self.buffer.push_str(&line);
+            self.buffer.push('\n');
+            if self.buffer.trim().is_empty() {
+                self.buffer.clear();
+                continue;
+            }
+            match classify(&self.buffer) {
+                ReplInput::Incomplete if !force => continue,
+                ReplInput::Incomplete => {
+                    if let Err(err) = parse_to_ast(&self.buffer) {
+                        eprintln!("{}", err);
+                    }
+                }
+                ReplInput::Invalid(err) => eprintln!("{}", err),
+                ReplInput::Expr(expr) => self.echo(expr),
+                ReplInput::Prog(ast) => self.eval(ast),
+            }
+            self.buffer.clear();
+        }
+    }
+
+    /* returns false when the session should end */
+    fn command(&mut self, line: &str) -> bool {
+        let (command, arg) = match line.find(char::is_whitespace) {
+            Some(index) => (&line[..index], line[index..].trim()),
+            None => (line, ""),
+        };
+        match (command, arg) {
+            (":state", "") => self.interpreter.state().print(),
+            (":funcs", "") => {
+                println!("------ FUNCTIONS ------");
+                for signature in self.interpreter.functions() {
+                    println!("{}", signature);
+                }
+            }
+            (":type", expr) if !expr.is_empty() => match parse_expr_to_ast(expr) {
+                Ok(expr) => match self.interpreter.type_of(&expr) {
+                    Ok(expr_type) => {
+                        print_warnings(self.interpreter.warnings());
+                        println!("{}", expr_type)
+                    }
+                    Err(errors) => print_type_errors(&errors),
+                },
+                Err(err) => eprintln!("{}", err),
+            },
+            (":load", file_path) if !file_path.is_empty() => match fs::read_to_string(file_path) {
+                Ok(unparsed_file) => match parse_to_ast(&unparsed_file) {
+                    Ok(ast) => self.eval(ast),
+                    Err(err) => eprintln!("{}: {}", file_path, err),
+                },
+                Err(err) => eprintln!("[Error] cannot read {}: {}", file_path, err),
+            },
+            (":help", "") => println!("{}", REPL_HELP),
+            (":quit", "") | (":q", "") => return false,
+            _ => eprintln!("[Error] unknown command `{}`, type :help for help", line),
+        }
+        true
+    }
+
+    fn echo(&mut self, expr: PistoletExpr) {
+        if let Err(errors) = self.interpreter.type_of(&expr) {
+            return print_type_errors(&errors);
+        }
+        print_warnings(self.interpreter.warnings());
+        match self.interpreter.eval_expr(expr) {
+            Ok(expr_value) => println!("{} : {}", expr_value.get_value(), expr_value.get_type()),
+            Err(err_code) => err_code.print(),
+        }
+    }
+
+    fn eval(&mut self, ast: PistoletAST) {
+        if let Err(errors) = self.interpreter.check(&ast) {
+            return print_type_errors(&errors);
+        }
+        print_warnings(self.interpreter.warnings());
+        match self.interpreter.run(ast) {
+            Ok(None) => {}
+            Ok(Some(expr_value)) => println!(
+                "[Return] Exit with {} : {}",
+                expr_value.get_type(),
+                expr_value.get_value()
+            ),
+            Err(err_code) => err_code.print(),
+        }
+    }
+}
+
+fn print_type_errors(errors: &[TypeErr]) {
+    for err in errors {
+        eprintln!("{}", err);
+    }
+}
+
+fn print_warnings(warnings: &[TypeWarning]) {
+    for warning in warnings {
+        eprintln!("{}", warning);
+    }
+}
+
+fn classify(input: &str) -> ReplInput {
+    let trimmed = input.trim_end();
+    let expr_err = match parse_expr_to_ast(trimmed.strip_suffix('.').unwrap_or(trimmed)) {
+        Ok(expr) => return ReplInput::Expr(expr),
+        Err(err) => err,
+    };
+    match parse_to_ast(input) {
+        Ok(ast) => ReplInput::Prog(ast),
+        Err(err) => {
+            let last_line = trimmed.lines().count();
+            let last_col = trimmed.lines().last().map_or(0, |l| l.chars().count());
+            if (err.line, err.col) > (last_line, last_col) || unclosed(trimmed) {
+                ReplInput::Incomplete
+            } else if (expr_err.line, expr_err.col) > (err.line, err.col) {
+                /* a statement fails at its first token, an expression gets further */
+                ReplInput::Invalid(expr_err)
+            } else {
+                ReplInput::Invalid(err)
+            }
+        }
+    }
+}
+
+fn unclosed(input: &str) -> bool {
+    let mut depth = 0;
+    for c in input.chars() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    depth > 0
+}
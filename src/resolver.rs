@@ -0,0 +1,333 @@
+use crate::ast::*;
+use crate::checker::TypeErr;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/* the variables one runtime frame holds, by name */
+#[derive(Debug, Clone, Default)]
+struct Frame {
+    var_list: HashMap<String, usize>,
+    size: usize,
+}
+
+impl Frame {
+    /* a name bound again in the same frame keeps its index */
+    fn declare(&mut self, name: &str) -> usize {
+        if let Some(index) = self.var_list.get(name) {
+            return *index;
+        }
+        let index = self.size;
+        self.var_list.insert(name.to_string(), index);
+        self.size += 1;
+        index
+    }
+}
+
+/*
+ * Works out where every variable will be at run time, as a (depth, index) slot,
+ * so the interpreter never looks a name up. Its frames follow the ones the
+ * interpreter pushes: the globals, each `if` branch, each `match` arm, each
+ * iteration of `while` and `for` (a `loop ... ~>` keeps one for all of them)
+ * and each function call, which also holds the parameters. A name that is no
+ * variable must be a function or a constructor, anything else is an error
+ * before the program runs, reported the way the checker reports it.
+ */
+#[derive(Debug, Clone)]
+pub(crate) struct Resolver {
+    frames: Vec<Frame>,              /* outermost first */
+    func_list: Vec<HashSet<String>>, /* functions and constructors of each enclosing block, outermost first */
+    errors: Vec<TypeErr>, /* names found undefined */
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            frames: vec![Frame::default()],
+            func_list: vec![HashSet::new()],
+            errors: Vec::new(),
+        }
+    }
+
+    /* resolves top-level statements against the globals, which keep them only if every name is found */
+    pub fn resolve(&mut self, term_list: &mut [PistoletAST]) -> Result<(), Vec<TypeErr>> {
+        let saved = (self.frames[0].clone(), self.func_list[0].clone());
+        self.hoist(term_list);
+        for term in term_list.iter_mut() {
+            self.ast(term);
+        }
+        self.finish(saved)
+    }
+
+    pub fn resolve_expr(&mut self, expr: &mut PistoletExpr) -> Result<(), Vec<TypeErr>> {
+        let saved = (self.frames[0].clone(), self.func_list[0].clone());
+        self.expr(expr);
+        self.finish(saved)
+    }
+
+    fn finish(&mut self, saved: (Frame, HashSet<String>)) -> Result<(), Vec<TypeErr>> {
+        self.frames.truncate(1);
+        self.func_list.truncate(1);
+        if self.errors.is_empty() {
+            return Ok(());
+        }
+        self.frames[0] = saved.0;
+        self.func_list[0] = saved.1;
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|err| err.span.start);
+        Err(errors)
+    }
+
+    /* functions and constructors are visible to the whole block defining them */
+    fn hoist(&mut self, term_list: &[PistoletAST]) {
+        let func_list = self.func_list.last_mut().unwrap();
+        for term in term_list {
+            match term {
+                PistoletAST::Fun(func_name, _, _, _, _) => {
+                    func_list.insert(func_name.clone());
+                }
+                PistoletAST::TypeDef(_, TypeDef::Variant(ctors), _) => {
+                    func_list.extend(ctors.iter().map(|(ctor, _)| ctor.clone()));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn find(&self, name: &str) -> Option<Slot> {
+        self.frames.iter().rev().enumerate().find_map(|(depth, frame)| {
+            frame.var_list.get(name).map(|index| Slot { depth, index: *index })
+        })
+    }
+
+    fn declare(&mut self, name: &str) -> usize {
+        self.frames.last_mut().unwrap().declare(name)
+    }
+
+    fn is_func(&self, name: &str) -> bool {
+        self.func_list.iter().any(|func_list| func_list.contains(name))
+    }
+
+    fn undefined(&mut self, name: &str, span: Span) {
+        self.errors.push(TypeErr {
+            message: format!("var `{}` used before defined", name),
+            span,
+        });
+    }
+
+    /* the statements of a block run in the frame around it, only its functions are its own */
+    fn block(&mut self, ast: &mut PistoletAST) {
+        if let PistoletAST::Seq(term_list, _) = ast {
+            self.func_list.push(HashSet::new());
+            self.hoist(term_list);
+            for term in term_list.iter_mut() {
+                self.ast(term);
+            }
+            self.func_list.pop();
+        }
+    }
+
+    /* a block in a frame of its own, which may start with some bindings */
+    fn framed_block(&mut self, frame: Frame, ast: &mut PistoletAST) {
+        self.frames.push(frame);
+        self.block(ast);
+        self.frames.pop();
+    }
+
+    fn function(&mut self, para_list: &PistoletAST, body: &mut Rc<PistoletAST>) {
+        let mut frame = Frame::default();
+        if let PistoletAST::Paralist(paras, _) = para_list {
+            for para in paras {
+                if let PistoletAST::Varbind(para_name, _, _) = para {
+                    frame.declare(para_name);
+                }
+            }
+        }
+        self.framed_block(frame, Rc::make_mut(body));
+    }
+
+    fn pattern(&mut self, pattern: &mut Pattern) {
+        match pattern {
+            Pattern::Var(var_name, index, _) => *index = self.declare(var_name),
+            Pattern::Tuple(patterns, _) => {
+                for pattern in patterns {
+                    self.pattern(pattern);
+                }
+            }
+            Pattern::Ctor(_, Some(arg), _) => self.pattern(arg),
+            Pattern::Ctor(_, None, _) | Pattern::Wildcard(_) | Pattern::Literal(_) => {}
+        }
+    }
+
+    fn ast(&mut self, ast: &mut PistoletAST) {
+        match ast {
+            PistoletAST::Seq(_, _) => self.block(ast),
+            PistoletAST::Let(pattern, _, _, var_expr, _) => {
+                self.expr(var_expr);
+                self.pattern(pattern);
+            }
+            PistoletAST::If(expr, branch_true, branch_false, _) => {
+                self.expr(expr);
+                self.framed_block(Frame::default(), branch_true);
+                self.framed_block(Frame::default(), branch_false);
+            }
+            PistoletAST::Match(expr, arms, _) => {
+                self.expr(expr);
+                for (pattern, guard, body) in arms {
+                    self.frames.push(Frame::default());
+                    self.pattern(pattern);
+                    if let Some(guard) = guard {
+                        self.expr(guard);
+                    }
+                    self.block(body);
+                    self.frames.pop();
+                }
+            }
+            /*
+             * The body of `loop ... ~>` runs again in the frame it bound its
+             * variables in, so a name it reads before binding it is the outside
+             * binding only the first time. Such names start out sharing the
+             * outside binding, which the body then replaces.
+             */
+            PistoletAST::While(seq, cond, _, carried, _) => {
+                let mut frame = Frame::default();
+                carried.clear();
+                for var_name in bound_names(seq) {
+                    if let Some(outside) = self.find(&var_name) {
+                        carried.push((frame.declare(&var_name), outside));
+                    }
+                }
+                self.frames.push(frame);
+                self.block(seq);
+                self.expr(cond);
+                self.frames.pop();
+            }
+            PistoletAST::WhileDo(cond, seq, _, _) => {
+                self.expr(cond);
+                self.framed_block(Frame::default(), seq);
+            }
+            PistoletAST::For(var_name, start, end, seq, _, _) => {
+                self.expr(start);
+                if let Some(end) = end {
+                    self.expr(end);
+                }
+                let mut frame = Frame::default();
+                frame.declare(var_name);
+                self.framed_block(frame, seq);
+            }
+            PistoletAST::Fun(_, para_list, _, fun_body, _) => self.function(para_list, fun_body),
+            PistoletAST::Return(expr, _) | PistoletAST::PrintLine(expr, _) | PistoletAST::Call(expr, _) => {
+                self.expr(expr)
+            }
+            PistoletAST::Assign(target, value, _) => {
+                self.expr(target);
+                self.expr(value);
+            }
+            PistoletAST::TypeDef(_, _, _)
+            | PistoletAST::Break(_, _)
+            | PistoletAST::Continue(_, _)
+            | PistoletAST::Varbind(_, _, _)
+            | PistoletAST::Paralist(_, _)
+            | PistoletAST::EOI => {}
+        }
+    }
+
+    fn exprs(&mut self, expr_list: &mut [PistoletExpr]) {
+        for expr in expr_list {
+            self.expr(expr);
+        }
+    }
+
+    fn expr(&mut self, expr: &mut PistoletExpr) {
+        match expr {
+            /* a variable holding a function hides a named function of the same name */
+            PistoletExpr::Val(PistoletValue::Funcall(func_name, expr_list, span)) => {
+                self.exprs(expr_list);
+                if let Some(slot) = self.find(func_name) {
+                    let callee = PistoletExpr::Val(PistoletValue::Var(func_name.clone(), Some(slot), *span));
+                    *expr = PistoletExpr::Call(Box::new(callee), std::mem::take(expr_list), *span);
+                }
+            }
+            PistoletExpr::Val(value) => match value {
+                PistoletValue::Var(var_name, slot, span) => {
+                    *slot = self.find(var_name);
+                    if slot.is_none() && !self.is_func(var_name) {
+                        let (var_name, span) = (var_name.clone(), *span);
+                        self.undefined(&var_name, span);
+                    }
+                }
+                PistoletValue::Array(expr_list, _) | PistoletValue::Tuple(expr_list, _) => self.exprs(expr_list),
+                PistoletValue::Record(_, fields, _) => {
+                    for (_, expr) in fields {
+                        self.expr(expr);
+                    }
+                }
+                PistoletValue::Funcall(_, _, _)
+                | PistoletValue::Integer(_, _)
+                | PistoletValue::Float(_, _)
+                | PistoletValue::Boolean(_, _)
+                | PistoletValue::Str(_, _) => {}
+            },
+            PistoletExpr::Neg(e, _) | PistoletExpr::Not(e, _) | PistoletExpr::Field(e, _, _) => self.expr(e),
+            PistoletExpr::Update(e, updates, _) => {
+                self.expr(e);
+                for (_, expr) in updates {
+                    self.expr(expr);
+                }
+            }
+            PistoletExpr::Match(e, arms, _) => {
+                self.expr(e);
+                for (pattern, guard, arm_value) in arms {
+                    self.frames.push(Frame::default());
+                    self.pattern(pattern);
+                    if let Some(guard) = guard {
+                        self.expr(guard);
+                    }
+                    self.expr(arm_value);
+                    self.frames.pop();
+                }
+            }
+            PistoletExpr::Lambda(para_list, _, body, _) => self.function(para_list, body),
+            PistoletExpr::Call(e, expr_list, _) => {
+                self.expr(e);
+                self.exprs(expr_list);
+            }
+            PistoletExpr::Index(e1, e2, _)
+            | PistoletExpr::Add(e1, e2, _)
+            | PistoletExpr::Sub(e1, e2, _)
+            | PistoletExpr::Mul(e1, e2, _)
+            | PistoletExpr::Div(e1, e2, _)
+            | PistoletExpr::And(e1, e2, _)
+            | PistoletExpr::Orb(e1, e2, _)
+            | PistoletExpr::Nand(e1, e2, _)
+            | PistoletExpr::Eq(e1, e2, _)
+            | PistoletExpr::Leq(e1, e2, _)
+            | PistoletExpr::Req(e1, e2, _)
+            | PistoletExpr::Left(e1, e2, _)
+            | PistoletExpr::Right(e1, e2, _) => {
+                self.expr(e1);
+                self.expr(e2);
+            }
+        }
+    }
+}
+
+/* the names the statements of a block bind in the frame it runs in */
+fn bound_names(ast: &PistoletAST) -> Vec<String> {
+    fn collect(pattern: &Pattern, names: &mut Vec<String>) {
+        match pattern {
+            Pattern::Var(var_name, _, _) => names.push(var_name.to_string()),
+            Pattern::Tuple(patterns, _) => patterns.iter().for_each(|pattern| collect(pattern, names)),
+            Pattern::Ctor(_, Some(arg), _) => collect(arg, names),
+            _ => {}
+        }
+    }
+    let mut names = Vec::new();
+    if let PistoletAST::Seq(term_list, _) = ast {
+        for term in term_list {
+            if let PistoletAST::Let(pattern, _, _, _, _) = term {
+                collect(pattern, &mut names);
+            }
+        }
+    }
+    names
+}
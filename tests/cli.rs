@@ -0,0 +1,152 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn pistolet(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_pistolet"))
+        .args(args)
+        .output()
+        .expect("cannot start pistolet")
+}
+
+/* a repl session with `input` typed in, the prompts end up in its stdout */
+fn repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_pistolet"))
+        .arg("repl")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("cannot start pistolet");
+    child
+        .stdin
+        .take()
+        .expect("no stdin")
+        .write_all(input.as_bytes())
+        .expect("cannot write to the repl");
+    child.wait_with_output().expect("repl did not finish")
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn run_exits_with_the_returned_value() {
+    assert_eq!(pistolet(&["run", "src/test/test_array.pst"]).status.code(), Some(43));
+    assert_eq!(pistolet(&["run", "src/test/test_string.pst"]).status.code(), Some(0));
+    let output = pistolet(&["help"]);
+    assert_eq!(output.status.code(), Some(0));
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: pistolet"));
+}
+
+#[test]
+fn bad_arguments_exit_with_64() {
+    for args in [
+        &[][..],
+        &["run"],
+        &["run", "--max-depth", "many", "src/test/test_array.pst"],
+        &["run", "src/test/test_array.pst", "src/test/test_for.pst"],
+        &["eval", "1 + 1"],
+        &["fly"],
+    ] {
+        let output = pistolet(args);
+        assert_eq!(output.status.code(), Some(64), "{:?}", args);
+        assert!(stderr(&output).starts_with("usage: pistolet"));
+    }
+}
+
+#[test]
+fn syntax_and_type_errors_exit_with_65() {
+    let output = pistolet(&["run", "src/test/test_check.pst"]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).starts_with("src/test/test_check.pst: [Error] 4:"));
+    assert_eq!(pistolet(&["check", "src/test/test_check.pst"]).status.code(), Some(65));
+    assert_eq!(pistolet(&["check", "src/test/test_eval.pst"]).status.code(), Some(0));
+    let output = pistolet(&["eval", "-e", "1 +"]);
+    assert_eq!(output.status.code(), Some(65));
+    assert!(stderr(&output).starts_with("[Error] line 1, column 4"));
+    assert_eq!(pistolet(&["eval", "-e", "1 + true"]).status.code(), Some(65));
+}
+
+#[test]
+fn missing_files_exit_with_66() {
+    for command in ["run", "check", "infer", "ast"] {
+        let output = pistolet(&[command, "src/test/no_such_file.pst"]);
+        assert_eq!(output.status.code(), Some(66), "{}", command);
+        assert!(stderr(&output).starts_with("[Error] cannot read src/test/no_such_file.pst"));
+    }
+}
+
+#[test]
+fn runtime_errors_exit_with_70_and_a_backtrace() {
+    let output = pistolet(&["run", "src/test/test_backtrace.pst"]);
+    assert_eq!(output.status.code(), Some(70));
+    assert_eq!(
+        stderr(&output),
+        "------ Runtime Error ------
+[Error] 2:36: attempt to divide by zero
+    in inner (called at 3:41)
+    in middle (called at 5:13)
+    in outer (called at 8:9)
+------ Runtime Error ------
+"
+    );
+    assert_eq!(pistolet(&["eval", "-e", "1 / 0"]).status.code(), Some(70));
+}
+
+#[test]
+fn returns_outside_the_exit_code_range_exit_with_70() {
+    /* test_eval returns 514, which the system would cut down to 2 */
+    let output = pistolet(&["run", "src/test/test_eval.pst"]);
+    assert_eq!(output.status.code(), Some(70));
+    assert!(stderr(&output).ends_with("[Error] cannot exit with 514, an exit code is from 0 to 255\n"));
+}
+
+#[test]
+fn repl_echoes_expressions_and_keeps_statements() {
+    let output = repl("1 + 2\nlet x = 4.\nx * 2.\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        stdout(&output),
+        "Pistolet REPL, type :help for help\npistolet> 3 : int\npistolet> pistolet> 8 : int\npistolet> \n"
+    );
+    assert_eq!(stderr(&output), "");
+}
+
+#[test]
+fn repl_reads_on_until_the_input_is_complete() {
+    /* an open block or bracket asks for more lines, the prompt shows it */
+    let output = repl("fun f (a: int) -> int {\n    return a + 1.\n}\nf(1)\n(1 +\n2)\n");
+    assert_eq!(
+        stdout(&output),
+        "Pistolet REPL, type :help for help
+pistolet>         |         | pistolet> 2 : int
+pistolet>         | 3 : int
+pistolet> \n"
+    );
+    assert_eq!(stderr(&output), "");
+    /* an empty line gives up on the unfinished input and reports why it does not parse */
+    let output = repl("fun g (a: int) -> int {\n\n:quit\n");
+    assert!(stdout(&output).ends_with("pistolet>         | pistolet> "));
+    assert!(stderr(&output).starts_with("[Error] line 3, column 1: expected"));
+}
+
+#[test]
+fn repl_reports_invalid_input_at_once() {
+    let output = repl("let y = * 2.\n1 + 1\n");
+    assert_eq!(
+        stdout(&output),
+        "Pistolet REPL, type :help for help\npistolet> pistolet> 2 : int\npistolet> \n"
+    );
+    assert_eq!(
+        stderr(&output),
+        "[Error] line 1, column 9: expected expression\n  |\n1 | let y = * 2.\n  |         ^\n"
+    );
+    /* a closing bracket too many is an error too, not a reason to wait */
+    let output = repl("1 + 2)\n");
+    assert!(stderr(&output).starts_with("[Error] line 1, column 6"));
+}
@@ -0,0 +1,28 @@
+use pistolet::{parse_expr_to_ast, PistoletExpr, PistoletValue};
+use std::fs;
+
+#[test]
+fn precedence_golden() {
+    let golden = fs::read_to_string("src/test/precedence.golden").expect("cannot read file");
+    for line in golden.lines().filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let (input, expected) = line.split_once(" => ").expect("malformed golden line");
+        match parse_expr_to_ast(input) {
+            Ok(expr) => assert_eq!(expr.to_string(), expected, "parsing `{}`", input),
+            Err(err) => panic!("`{}`: {}", input, err),
+        }
+    }
+}
+
+#[test]
+fn boolean_literals_are_not_variables() {
+    match parse_expr_to_ast("x =? true").unwrap() {
+        PistoletExpr::Eq(_, rhs, _) => {
+            assert!(matches!(*rhs, PistoletExpr::Val(PistoletValue::Boolean(true, _))))
+        }
+        other => panic!("unexpected parse {}", other),
+    }
+    assert!(matches!(
+        parse_expr_to_ast("trueish").unwrap(),
+        PistoletExpr::Val(PistoletValue::Var(_, _, _))
+    ));
+}
@@ -0,0 +1,933 @@
+use pistolet::{
+    check_program, parse_expr_to_ast, parse_to_ast, Checker, Interpreter, PistoletAST, PistoletExpr,
+    PistoletValue, RunErr, RuntimeErr, Type, VarValue, DEFAULT_MAX_DEPTH,
+};
+use std::fs;
+
+fn load(file_path: &str) -> PistoletAST {
+    let unparsed_file = fs::read_to_string(file_path).expect("cannot read file");
+    match parse_to_ast(&unparsed_file) {
+        Ok(ast) => ast,
+        Err(err) => panic!("{}: {}", file_path, err),
+    }
+}
+
+/* the int a program returns, it must type check first */
+fn returns_int(ast: PistoletAST) -> i128 {
+    if let Err(errors) = check_program(&ast) {
+        panic!("{}", errors[0]);
+    }
+    match Interpreter::new().run(ast) {
+        Ok(Some(expr_value)) => match expr_value.get_value() {
+            VarValue::Int(n) => n,
+            other => panic!("unexpected return value {}", other),
+        },
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+/* the messages of the type errors in a program, which must have some */
+fn type_errors(src: &str) -> Vec<String> {
+    let errors = check_program(&parse_to_ast(src).unwrap()).unwrap_err();
+    errors.iter().map(|err| err.message.clone()).collect()
+}
+
+/* the error a program or expression stopped with while running */
+fn runtime_err<T: std::fmt::Debug>(result: Result<T, RunErr>) -> RuntimeErr {
+    match result {
+        Err(RunErr::Runtime(err)) => err,
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn parser_test() {
+    load("src/test/test_parser.pst");
+}
+
+#[test]
+fn interpreter_test() {
+    assert_eq!(returns_int(load("src/test/test_eval.pst")), 514);
+}
+
+#[test]
+fn checker_accepts_eval_test() {
+    if let Err(errors) = check_program(&load("src/test/test_eval.pst")) {
+        panic!("{}", errors[0]);
+    }
+}
+
+#[test]
+fn checker_reports_every_error() {
+    let errors = check_program(&load("src/test/test_check.pst")).unwrap_err();
+    let lines: Vec<usize> = errors.iter().map(|err| err.span.line).collect();
+    assert_eq!(lines, vec![4, 12, 18, 19, 21, 26, 26]);
+}
+
+#[test]
+fn checker_infers_signatures() {
+    let mut checker = Checker::new();
+    if let Err(errors) = checker.check(&load("src/test/test_infer.pst")) {
+        panic!("{}", errors[0]);
+    }
+    assert_eq!(
+        checker.signatures(),
+        vec![
+            "fun id (x: 'a) -> 'a",
+            "fun add (a: 'a)(b: 'a) -> 'a where 'a is int, float or string",
+            "fun fact (n: int) -> int",
+            "fun pick (flag: bool)(a: 'a)(b: 'a) -> 'a",
+        ]
+    );
+}
+
+#[test]
+fn inferred_program_runs() {
+    match Interpreter::new().run(load("src/test/test_infer.pst")) {
+        Ok(Some(expr_value)) => assert_eq!(expr_value.get_type(), Type::Int),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn checker_rejects_misused_inferred_types() {
+    let messages = type_errors(
+        "fun add (a)(b) { return a + b. }
+         let x = add(true, false).
+         let y = add(1, 2).
+         let z: float = y.",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "argument of `add` must be int, float or string but is bool",
+            "argument of `add` must be int, float or string but is bool",
+            "`z` is declared float but is bound to int",
+        ]
+    );
+}
+
+#[test]
+fn checker_rejects_unknown_types() {
+    let messages = type_errors(
+        "let a: Int = 1.
+         let b: INT = 2.
+         let c: int = 3.
+         fun f (x: nat) -> bool { return true. }",
+    );
+    assert_eq!(messages, vec!["unknown type `Int`", "unknown type `INT`", "unknown type `nat`"]);
+
+    let errors = check_program(&load("src/test/test_parser.pst")).unwrap_err();
+    assert!(errors.iter().any(|err| err.message == "unknown type `nat`"));
+}
+
+#[test]
+fn string_test() {
+    let ast = load("src/test/test_string.pst");
+    if let Err(errors) = check_program(&ast) {
+        panic!("{}", errors[0]);
+    }
+    match Interpreter::new().run(ast) {
+        Ok(Some(expr_value)) => match expr_value.get_value() {
+            VarValue::Str(s) => assert_eq!(s, "Hello, world!!"),
+            other => panic!("unexpected return value {}", other),
+        },
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn string_escapes() {
+    let ast = parse_to_ast(r#"let s = "a\tb\n\"c\"\\\u{e9}\0"."#).unwrap();
+    match ast {
+        PistoletAST::Seq(term_list, _) => match &term_list[0] {
+            PistoletAST::Let(_, _, _, PistoletExpr::Val(PistoletValue::Str(s, _)), _) => {
+                assert_eq!(s, "a\tb\n\"c\"\\\u{e9}\0")
+            }
+            other => panic!("unexpected parse {:?}", other),
+        },
+        other => panic!("unexpected parse {:?}", other),
+    }
+    assert!(parse_to_ast(r#"let s = "bad \q escape"."#).is_err());
+    assert!(parse_to_ast("let s = \"unterminated.\n").is_err());
+}
+
+#[test]
+fn array_test() {
+    let ast = load("src/test/test_array.pst");
+    assert_eq!(returns_int(ast), 43);
+}
+
+#[test]
+fn array_index_out_of_bounds() {
+    let ast = parse_to_ast("let a = [1, 2, 3]. let i = 3. println(a[i]).").unwrap();
+    match runtime_err(Interpreter::new().run(ast)) {
+        RuntimeErr::IndexOutOfBounds(index, len, span) => {
+            assert_eq!((index, len), (3, 3));
+            assert_eq!(span.col, 41);
+        }
+        other => panic!("unexpected error {}", other),
+    }
+}
+
+#[test]
+fn checker_rejects_misused_arrays() {
+    let messages = type_errors(
+        "let a = [1, true].
+         let b = 1.
+         println(b[0]).
+         let c = [1, 2].
+         println(c[true]).
+         c[0] := 2.5.
+         push(c, \"x\").",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "array elements have different types int and bool",
+            "cannot index into int",
+            "index must be int but is bool",
+            "cannot assign float to an element of type int",
+            "argument of `push` must be int but is string",
+        ]
+    );
+}
+
+#[test]
+fn tuple_test() {
+    let ast = load("src/test/test_tuple.pst");
+    let mut checker = Checker::new();
+    if let Err(errors) = checker.check(&ast) {
+        panic!("{}", errors[0]);
+    }
+    assert_eq!(checker.signatures()[1], "fun swap (p: ('a, 'b)) -> ('b, 'a)");
+    assert_eq!(returns_int(ast), 32);
+}
+
+#[test]
+fn checker_rejects_misused_tuples() {
+    let messages = type_errors(
+        "let (a, b) = (1, 2, 3).
+         let (c, d) = 4.
+         let t: (int, bool) = (true, 1).",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "cannot take (int, int, int) apart into 2 names",
+            "cannot take int apart into 2 names",
+            "`t` is declared (int, bool) but is bound to (bool, int)",
+        ]
+    );
+}
+
+#[test]
+fn record_test() {
+    let ast = load("src/test/test_record.pst");
+    let mut checker = Checker::new();
+    if let Err(errors) = checker.check(&ast) {
+        panic!("{}", errors[0]);
+    }
+    assert_eq!(checker.signatures()[0], "fun norm2 (p: Point) -> float");
+    assert_eq!(returns_int(ast), 1);
+}
+
+#[test]
+fn checker_rejects_misused_records() {
+    let messages = type_errors(
+        "type Point = { x: float, y: float }
+         type Size = { x: int, h: int }
+         let a = Point { x = 1, y = 2.0 }.
+         let b = Point { x = 1.0 }.
+         let c = Point { x = 1.0, y = 2.0, y = 3.0 }.
+         let d = a.z.
+         let e = { a with x = true }.
+         let f = Pointe { x = 1.0 }.
+         fun getx (r) { return r.x. }",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "field `x` of `Point` must be float but is int",
+            "`Point` is missing field `y`",
+            "field `y` is set twice",
+            "`Point` has no field `z`",
+            "field `x` of `Point` must be float but is bool",
+            "unknown type `Pointe`",
+            "field `x` could belong to any of Point, Size, annotate which one",
+        ]
+    );
+}
+
+#[test]
+fn match_test() {
+    let ast = load("src/test/test_match.pst");
+    let mut checker = Checker::new();
+    if let Err(errors) = checker.check(&ast) {
+        panic!("{}", errors[0]);
+    }
+    assert!(checker.warnings().is_empty());
+    assert_eq!(checker.signatures()[1], "fun insert (t: tree)(n: int) -> tree");
+    assert_eq!(returns_int(ast), 27);
+}
+
+#[test]
+fn checker_warns_about_non_exhaustive_matches() {
+    let ast = parse_to_ast(
+        "type shape = Circle of float | Rect of float * float | Dot
+         type tree = Leaf | Node of tree * int * tree
+         let s = Dot.
+         let t = Leaf.
+         let a = match s with | Circle _ -> 1 | Dot -> 2.
+         let b = match t with | Leaf -> 0 | Node (Leaf, _, _) -> 1.
+         let c = match (true, false) with | (true, _) -> 0 | (_, true) -> 1.
+         let d = match 3 with | 0 -> 0 | n when n > 0 -> 1.
+         let e = match s with | Circle r -> r | Rect (w, h) -> w | Dot -> 0.0.",
+    )
+    .unwrap();
+    let mut checker = Checker::new();
+    checker.check(&ast).unwrap();
+    let messages: Vec<String> = checker.warnings().iter().map(|w| w.message.clone()).collect();
+    assert_eq!(
+        messages,
+        vec![
+            "match is not exhaustive, `Rect (_, _)` is not handled",
+            "match is not exhaustive, `Node (Node (_, _, _), _, _)` is not handled",
+            "match is not exhaustive, `(false, false)` is not handled",
+            "match is not exhaustive, `_` is not handled",
+        ]
+    );
+}
+
+#[test]
+fn checker_rejects_misused_variants() {
+    let messages = type_errors(
+        "type shape = Circle of float | Rect of float * float
+         let a = Circle(1).
+         let b = Rect(1.0).
+         let c = Circle.
+         let d = match a with | Circle r -> r | Rect (w, h, z) -> w.
+         let e = match a with | Square -> 1 | _ -> 2.
+         let f = match a with | Circle _ -> 1 | _ -> \"x\".
+         let g = match 3 with | Circle _ -> 1 | _ -> 2.
+         let h = match a with | Circle r when r -> 1 | _ -> 2.",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "argument of `Circle` must be float but is int",
+            "constructor `Rect` takes 2 arguments but 1 were given",
+            "constructor `Circle` takes 1 arguments but 0 were given",
+            "constructor `Rect` takes 2 arguments",
+            "unknown constructor `Square`",
+            "match arms have different types int and string",
+            "constructor `Circle` makes shape but the value matched is int",
+            "guard must be bool but is float",
+        ]
+    );
+}
+
+#[test]
+fn unmatched_value_is_a_runtime_error() {
+    let ast = parse_to_ast(
+        "type shape = Circle of float | Rect of float * float
+         let s = Rect(1.0, 2.0).
+         match s with | Circle r -> { println(r). }",
+    )
+    .unwrap();
+    match runtime_err(Interpreter::new().run(ast)).root() {
+        RuntimeErr::MatchFailure(value, span) => {
+            assert_eq!(value, "Rect(1, 2)");
+            assert_eq!(span.line, 3);
+        }
+        other => panic!("unexpected error {}", other),
+    }
+}
+
+#[test]
+fn closure_test() {
+    let ast = load("src/test/test_closure.pst");
+    let mut checker = Checker::new();
+    if let Err(errors) = checker.check(&ast) {
+        panic!("{}", errors[0]);
+    }
+    assert_eq!(
+        checker.signatures(),
+        vec![
+            "fun make_adder (n: int) -> int -> int",
+            "fun apply (f: int -> int)(x: int) -> int",
+            "fun compose (f: int -> int)(g: int -> int) -> int -> int",
+            "fun double (x: int) -> int",
+        ]
+    );
+    assert_eq!(returns_int(ast), 31);
+}
+
+#[test]
+fn checker_rejects_misused_functions() {
+    let messages = type_errors(
+        "fun double (x: int) -> int { return x * 2. }
+         let f = fun (x: int) -> int { return x + 1. }.
+         let a = f(true).
+         let b = f(1, 2).
+         let c = 3.
+         let d = c(1).
+         let g: int -> bool = double.
+         let h = fun (x: int) -> int { println(x). }.
+         fun twice (k)(x: int) { return k(k(x)). }
+         let e = twice(1, 2).",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "argument of `f` must be int but is bool",
+            "function `f` takes 1 arguments but 2 were given",
+            "`c` is int, not a function",
+            "`g` is declared int -> bool but is bound to int -> int",
+            "anonymous function may finish without return",
+            "argument of `twice` must be int -> int but is int",
+        ]
+    );
+}
+
+#[test]
+fn functions_are_lexically_scoped() {
+    let ast = load("src/test/test_scope.pst");
+    /* dynamic scoping would give 100 + 22 + 1000 + 7 */
+    assert_eq!(returns_int(ast), 1 + 12 + 1 + 7);
+}
+
+#[test]
+fn function_cannot_see_callers_locals() {
+    let ast = parse_to_ast(
+        "fun read_z (u: int) -> int { return z. }
+         fun caller (u: int) -> int { let z = 5. return read_z(u). }
+         return caller(0).",
+    )
+    .unwrap();
+    let errors = check_program(&ast).unwrap_err();
+    assert_eq!(errors[0].message, "var `z` used before defined");
+    match Interpreter::new().run(ast) {
+        Err(RunErr::Unresolved(errors)) => {
+            assert_eq!(errors.len(), 1);
+            assert_eq!(errors[0].message, "var `z` used before defined");
+            assert_eq!(errors[0].span.line, 1);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn mutable_assignment_test() {
+    let ast = load("src/test/test_mut.pst");
+    assert_eq!(returns_int(ast), 40 + 16 + 20);
+}
+
+#[test]
+fn checker_rejects_bad_assignments() {
+    let messages = type_errors(
+        "let x = 1.
+         x := 2.
+         let mut y = 1.
+         y := \"one\".
+         z := 3.
+         let mut w = 1.
+         let w = 2.
+         w := 3.
+         fun f (n: int) -> int { n := 1. return n. }",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "cannot assign to `x`, it is not declared with `let mut`",
+            "cannot assign string to `y` of type int",
+            "var `z` used before defined",
+            "cannot assign to `w`, it is not declared with `let mut`",
+            "cannot assign to `n`, it is not declared with `let mut`",
+        ]
+    );
+}
+
+#[test]
+fn assigning_an_immutable_binding_is_a_runtime_error() {
+    let ast = parse_to_ast(
+        "let x = 1.
+         x := 2.",
+    )
+    .unwrap();
+    match runtime_err(Interpreter::new().run(ast)) {
+        RuntimeErr::AssignImmutable(var_name, span) => {
+            assert_eq!(var_name, "x");
+            assert_eq!(span.line, 2);
+        }
+        other => panic!("unexpected error {}", other),
+    }
+}
+
+#[test]
+fn loop_control_test() {
+    let ast = load("src/test/test_break.pst");
+    assert_eq!(returns_int(ast), 8 + 30 + 8 + 4);
+}
+
+#[test]
+fn checker_rejects_misplaced_jumps() {
+    let messages = type_errors(
+        "break.
+         fun f (x: int) -> int { loop { continue 'outer. } ~> true return x. }
+         'outer: loop { fun g (y: int) -> int { break. return y. } break 'inner. } ~> true
+         fun k (x: int) -> int { loop { if x > 0 { break. } { println(x). } return 1. } ~> true }
+         fun forever (x: int) -> int { loop { println(x). } ~> false }",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "`break` outside a loop",
+            "no loop labeled `'outer`",
+            "`break` outside a loop",
+            "no loop labeled `'inner`",
+            "function `k` may finish without return",
+        ]
+    );
+}
+
+#[test]
+fn break_cannot_leave_a_function() {
+    let ast = parse_to_ast(
+        "fun stop (x: int) -> int { break. }
+         loop { stop(1). } ~> true",
+    )
+    .unwrap();
+    match runtime_err(Interpreter::new().run(ast)).root() {
+        RuntimeErr::Break(None, span) => assert_eq!(span.line, 1),
+        other => panic!("unexpected error {}", other),
+    }
+}
+
+#[test]
+fn for_and_while_test() {
+    let ast = load("src/test/test_for.pst");
+    assert_eq!(returns_int(ast), 10 + 30 + 111 + 2 + 10);
+}
+
+#[test]
+fn checker_rejects_misused_loops() {
+    let messages = type_errors(
+        "for i in 0..2.5 { println(i). }
+         for x in 5 { println(x). }
+         while 1 { println(1). }
+         for i in 0..3 { println(i). }
+         println(i).
+         for j in 0..3 { j := 1. }
+         fun f (x: int) -> int { while x > 0 { return 1. } }
+         fun g (x: int) -> int { while true { println(x). } }",
+    );
+    assert_eq!(
+        messages,
+        vec![
+            "range bound must be int but is float",
+            "can only loop over arrays, not int",
+            "loop condition must be bool but is int",
+            "var `i` used before defined",
+            "cannot assign to `j`, it is not declared with `let mut`",
+            "function `f` may finish without return",
+        ]
+    );
+}
+
+#[test]
+fn checker_rejects_stray_returns() {
+    let ast = parse_to_ast(
+        "let x = 1.
+         return x.
+         loop { return 2. } ~> true
+         if x > 0 { return 3. } { println(x). }
+         match x with
+         | 1 -> { return 4. }
+         | _ -> { if x > 1 { return 5. } { return 6. } }",
+    )
+    .unwrap();
+    let errors = check_program(&ast).unwrap_err();
+    let lines: Vec<usize> = errors.iter().map(|err| err.span.line).collect();
+    assert_eq!(lines, vec![2, 3, 4]);
+    assert!(errors.iter().all(|err| err.message == "`return` outside a function"));
+}
+
+#[test]
+fn return_ends_the_program_from_a_loop() {
+    let ast = parse_to_ast(
+        "fun first_over (xs: array<int>)(n: int) -> int {
+             for x in xs { if x > n { return x. } { println(x). } }
+             return 0.
+         }
+         return first_over([1, 5, 9], 4).",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 5);
+}
+
+#[test]
+fn undefined_variables_are_caught_before_running() {
+    let mut interpreter = Interpreter::new();
+    let ast = parse_to_ast("let a = 1. fun f (x: int) -> int { return x + b. } return f(a) + c.").unwrap();
+    match interpreter.run(ast) {
+        Err(RunErr::Unresolved(errors)) => {
+            let found: Vec<(String, usize)> = errors.iter().map(|err| (err.to_string(), err.span.col)).collect();
+            assert_eq!(
+                found,
+                vec![
+                    ("[Error] 1:47: var `b` used before defined".to_string(), 47),
+                    ("[Error] 1:66: var `c` used before defined".to_string(), 66)
+                ]
+            );
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+    /* nothing ran, and the session goes on without the rejected globals */
+    assert!(interpreter.state().find_var("a", Default::default()).is_err());
+    match interpreter.run(parse_to_ast("let b = 2. return b.").unwrap()) {
+        Ok(Some(expr_value)) => assert!(matches!(expr_value.get_value(), VarValue::Int(2))),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn loop_body_reads_the_outside_binding_until_it_rebinds_it() {
+    let ast = parse_to_ast(
+        "let k = 0.
+         let mut total = 0.
+         loop {
+             loop { let k = k + 10. total := total + k. } ~> (k > 25)
+             let k = k + 1.
+         } ~> (k =? 3)
+         let mut fs = [fun (x: int) -> int { return x. }].
+         let j = 0.
+         loop { let j = j + 1. push(fs, fun (x: int) -> int { return x + j. }). } ~> (j =? 3)
+         return total * 1000 + fs[1](0) + fs[2](0) * 10 + fs[3](0) * 100.",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 189 * 1000 + 321);
+}
+
+#[test]
+fn deep_recursion_grows_the_stack() {
+    let ast = parse_to_ast(
+        "fun sum (n: int) -> int { if n =? 0 { return 0. } { return n + sum(n - 1). } }
+         return sum(5000).",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 5000 * 5001 / 2);
+}
+
+#[test]
+fn runaway_recursion_is_a_stack_overflow() {
+    let program = "fun down (n: int) -> int { return 1 + down(n + 1). }
+                   return down(0).";
+    let mut interpreter = Interpreter::new();
+    interpreter.set_max_depth(100);
+    let err = runtime_err(interpreter.run(parse_to_ast(program).unwrap()));
+    match err.root() {
+        RuntimeErr::StackOverflow(max_depth, span) => {
+            assert_eq!(*max_depth, 100);
+            assert_eq!(span.line, 1);
+        }
+        other => panic!("unexpected error {}", other),
+    }
+    assert_eq!(err.backtrace().len(), 100);
+    match Interpreter::new().run(parse_to_ast(program).unwrap()) {
+        Err(RunErr::Runtime(err)) => {
+            assert!(matches!(err.root(), RuntimeErr::StackOverflow(DEFAULT_MAX_DEPTH, _)));
+            assert!(err.to_string().ends_with("stack overflow, more than 10000 function calls active at once"));
+            assert_eq!(err.backtrace().len(), DEFAULT_MAX_DEPTH);
+        }
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn tail_calls_run_in_constant_stack() {
+    let ast = parse_to_ast(
+        "fun count (n: int)(acc: int) -> int {
+             if n =? 0 { return acc. } { return count(n - 1, acc + 1). }
+         }
+         return count(1000000, 0).",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 1000000);
+}
+
+#[test]
+fn mutual_tail_calls_through_branches() {
+    let ast = parse_to_ast(
+        "fun is_even (n: int) -> bool {
+             if n =? 0 { return true. } { return is_odd(n - 1). }
+         }
+         fun is_odd (n: int) -> bool {
+             match n with
+             | 0 -> { return false. }
+             | _ -> { return is_even(n - 1). }
+         }
+         return is_odd(1000001).",
+    )
+    .unwrap();
+    if let Err(errors) = check_program(&ast) {
+        panic!("{}", errors[0]);
+    }
+    match Interpreter::new().run(ast) {
+        Ok(Some(expr_value)) => assert!(matches!(expr_value.get_value(), VarValue::Bool(true))),
+        other => panic!("unexpected result {:?}", other),
+    }
+}
+
+#[test]
+fn tail_call_result_must_fit_the_callers_return_type() {
+    let ast = parse_to_ast(
+        "fun call (f: int -> string)(x: int) -> int { return f(x). }
+         return call(fun (n: int) -> string { return \"n\". }, 1).",
+    )
+    .unwrap();
+    let err = runtime_err(Interpreter::new().run(ast));
+    match err.root() {
+        RuntimeErr::TypeMismatch(expected, found, span) => {
+            assert_eq!((expected.as_str(), found.as_str()), ("int", "string"));
+            assert_eq!((span.line, span.col), (1, 53));
+        }
+        other => panic!("unexpected error {}", other),
+    }
+    let frames: Vec<String> = err.backtrace().into_iter().map(|(func_name, _)| func_name).collect();
+    assert_eq!(frames, vec!["call"]);
+}
+
+#[test]
+fn functions_and_constructors_can_be_used_before_their_definition() {
+    let ast = parse_to_ast(
+        "let base = 10.
+         println(f(1)).
+         println(area(Sq(2))).
+         fun f (x: int) -> int { return x + base + g(x). }
+         fun g (x: int) -> int { return x * 100. }
+         type shape = Sq of int
+         fun area (s: shape) -> int { return match s with | Sq n -> n * n. }
+         let base = 20.
+         return f(2).",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 212);
+}
+
+#[test]
+fn long_variant_lists_are_shared_not_copied() {
+    let ast = parse_to_ast(
+        "type list = Nil | Cons of int * list
+         fun build (n: int)(acc: list) -> list {
+             if n =? 0 { return acc. } { return build(n - 1, Cons(n, acc)). }
+         }
+         fun length (l: list)(acc: int) -> int {
+             match l with
+             | Nil -> { return acc. }
+             | Cons (_, rest) -> { return length(rest, acc + 1). }
+         }
+         let l = build(100000, Nil).
+         return length(l, 0).",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 100000);
+}
+
+#[test]
+fn integer_overflow_is_a_runtime_error() {
+    let ast = parse_to_ast(
+        "fun factorial (n: int) -> int {
+             if n =? 0 { return 1. } { return n * factorial(n - 1). }
+         }
+         return factorial(40).",
+    )
+    .unwrap();
+    let err = runtime_err(Interpreter::new().run(ast));
+    match err.root() {
+        RuntimeErr::Overflow(span) => assert_eq!((span.line, span.col), (2, 47)),
+        other => panic!("unexpected error {}", other),
+    }
+    /* 34! is the first factorial past the largest int, so factorial(34) to factorial(40) */
+    assert_eq!(err.backtrace().len(), 7);
+    let max = "170141183460469231731687303715884105727";
+    for expr in &[format!("{} + 1", max), format!("-{} - 2", max), format!("-(-{} - 1)", max), format!("{} * 2", max)] {
+        let expr = parse_expr_to_ast(expr).unwrap();
+        match runtime_err(Interpreter::new().eval_expr(expr)) {
+            RuntimeErr::Overflow(_) => {}
+            other => panic!("unexpected error {}", other),
+        }
+    }
+}
+
+#[test]
+fn tail_calls_through_new_lambdas_keep_one_return_type() {
+    let ast = parse_to_ast(
+        "fun countdown (n: int) -> int {
+             let step = fun (m: int) -> int { return countdown(m - 1). }.
+             if n =? 0 { return 0. } { return step(n). }
+         }
+         return countdown(100000).",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 0);
+}
+
+#[test]
+fn tail_calls_through_returned_match_arms() {
+    let ast = parse_to_ast(
+        "fun count (n: int)(acc: int) -> int {
+             return match n with
+             | 0 -> acc
+             | _ -> count(n - 1, acc + 1).
+         }
+         return count(1000000, 0).",
+    )
+    .unwrap();
+    assert_eq!(returns_int(ast), 1000000);
+}
+
+#[test]
+fn parse_error_points_at_a_typo() {
+    let err = parse_to_ast("let x = 1.\nlet y = x + * 2.\nreturn y.").unwrap_err();
+    assert_eq!((err.line, err.col), (2, 13));
+    assert_eq!(err.expected, vec!["value", "prefix operator"]);
+    assert_eq!(err.snippet(), "  |\n2 | let y = x + * 2.\n  |             ^");
+    assert_eq!(
+        err.to_string(),
+        "[Error] line 2, column 13: expected value, prefix operator\n  |\n2 | let y = x + * 2.\n  |             ^"
+    );
+}
+
+#[test]
+fn parse_error_at_an_unterminated_block() {
+    let err = parse_to_ast("fun f (x: int) -> int {\n    return x.\n").unwrap_err();
+    assert_eq!((err.line, err.col), (3, 1));
+    assert!(err.expected.contains(&"`return`".to_string()));
+    assert_eq!(err.expected.last().map(String::as_str), Some("`}`"));
+    assert_eq!(err.snippet(), "  |\n3 | \n  | ^");
+}
+
+#[test]
+fn parse_error_in_an_expression() {
+    let err = parse_expr_to_ast("1 + (2 * 3").unwrap_err();
+    assert_eq!((err.line, err.col), (1, 11));
+    assert!(err.expected.contains(&"`)`".to_string()));
+    assert_eq!(err.snippet(), "  |\n1 | 1 + (2 * 3\n  |           ^");
+    let err = parse_expr_to_ast("1 +* 2").unwrap_err();
+    assert_eq!((err.line, err.col), (1, 4));
+    assert_eq!(err.message, "expected value, prefix operator");
+}
+
+#[test]
+fn spans_point_at_their_source() {
+    let src = "let x = 1.\nfun f (a: int) -> int {\n    return a * (x + 2).\n}\nprintln(f(3)).";
+    let statements = match parse_to_ast(src).unwrap() {
+        PistoletAST::Seq(statements, _) => statements,
+        other => panic!("unexpected program {:?}", other),
+    };
+    let span = statements[0].span();
+    assert_eq!((span.line, span.col), (1, 1));
+    assert_eq!(&src[span.start..span.end], "let x = 1.");
+    let body = match &statements[1] {
+        PistoletAST::Fun(name, _, _, body, span) => {
+            assert_eq!(name, "f");
+            assert_eq!((span.line, span.col), (2, 1));
+            body.clone()
+        }
+        other => panic!("unexpected statement {:?}", other),
+    };
+    let value = match &*body {
+        PistoletAST::Seq(statements, _) => match &statements[0] {
+            PistoletAST::Return(value, span) => {
+                assert_eq!((span.line, span.col), (3, 5));
+                value.clone()
+            }
+            other => panic!("unexpected statement {:?}", other),
+        },
+        other => panic!("unexpected body {:?}", other),
+    };
+    match value {
+        PistoletExpr::Mul(left, right, span) => {
+            assert_eq!(&src[span.start..span.end], "a * (x + 2)");
+            assert_eq!((left.span().line, left.span().col), (3, 12));
+            assert_eq!(&src[right.span().start..right.span().end], "(x + 2)");
+            assert_eq!((right.span().line, right.span().col), (3, 16));
+        }
+        other => panic!("unexpected expression {:?}", other),
+    }
+    let span = statements[2].span();
+    assert_eq!((span.line, span.col), (5, 1));
+}
+
+#[test]
+fn runtime_error_backtrace_lists_each_call() {
+    let ast = parse_to_ast(
+        "fun inner (n: int) -> int { return 10 / n. }
+fun middle (n: int) -> int { return 1 + inner(n). }
+fun outer (n: int) -> int {
+    let m = middle(n - 1).
+    return m.
+}
+println(outer(1)).",
+    )
+    .unwrap();
+    let err = runtime_err(Interpreter::new().run(ast));
+    match err.root() {
+        RuntimeErr::DivideByZero(span) => assert_eq!((span.line, span.col), (1, 36)),
+        other => panic!("unexpected error {}", other),
+    }
+    assert_eq!(err.to_string(), "1:36: attempt to divide by zero");
+    let frames: Vec<(String, usize, usize)> = err
+        .backtrace()
+        .into_iter()
+        .map(|(name, span)| (name, span.line, span.col))
+        .collect();
+    /* innermost first, each with the place it was called from */
+    assert_eq!(
+        frames,
+        vec![
+            ("inner".to_string(), 2, 41),
+            ("middle".to_string(), 4, 13),
+            ("outer".to_string(), 7, 9)
+        ]
+    );
+}
+
+#[test]
+fn deep_lists_print_without_overflowing() {
+    let program = "type list = Nil | Cons of int * list
+         fun build (n: int)(acc: list) -> list {
+             if n =? 0 { return acc. } { return build(n - 1, Cons(n, acc)). }
+         }
+         let l = build(200000, Nil).
+         println(l).
+         match l with | Nil -> { println(0). }";
+    let printed = match runtime_err(Interpreter::new().run(parse_to_ast(program).unwrap())).root() {
+        RuntimeErr::MatchFailure(value, _) => value.clone(),
+        other => panic!("unexpected error {}", other),
+    };
+    assert!(printed.starts_with("Cons(1, Cons(2, Cons(3, "));
+    assert!(printed.ends_with(&format!("Cons(200000, Nil{}", ")".repeat(200000))));
+}
+
+#[test]
+fn argument_mismatch_names_the_parameter_and_the_called_function() {
+    let ast = parse_to_ast(
+        "fun half (n: int) -> int { return n / 2. }
+fun twice (f: int -> int)(x: int) -> int { return f(f(x)). }
+println(twice(half, \"four\")).",
+    )
+    .unwrap();
+    let err = runtime_err(Interpreter::new().run(ast));
+    assert_eq!(
+        err.to_string(),
+        "3:21: parameter `x` of function `twice` expects int but found string"
+    );
+    let frames: Vec<(String, usize, usize)> = err
+        .backtrace()
+        .into_iter()
+        .map(|(name, span)| (name, span.line, span.col))
+        .collect();
+    assert_eq!(frames, vec![("twice".to_string(), 3, 9)]);
+}